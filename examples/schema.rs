@@ -1,20 +1,85 @@
 use std::env::current_dir;
 use std::fs::create_dir_all;
+use std::path::Path;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use cw_vesting::msg::{ExecuteMsg, InstantiateMsg, PaymentsResponse, QueryMsg};
+use cw_vesting::msg::{
+    AddPaymentsResponse, BalancesResponse, ClaimableResponse, ConfigResponse, DueCountResponse,
+    ExecuteMsg, GroupedPaymentsResponse, InstantiateMsg, MigrateMsg, NextUnlockResponse,
+    OutstandingByRecipientResponse, PaymentsCountResponse, PaymentsResponse, ProgressResponse,
+    QueryMsg, RecipientSummaryResponse, RecipientsResponse, SimulatePayResponse, StateResponse,
+    StatsResponse, StopPaymentResponse,
+};
 use cw_vesting::state::PaymentState;
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
     out_dir.push("schema");
-    create_dir_all(&out_dir).unwrap();
-    remove_schemas(&out_dir).unwrap();
-
-    export_schema(&schema_for!(InstantiateMsg), &out_dir);
-    export_schema(&schema_for!(ExecuteMsg), &out_dir);
-    export_schema(&schema_for!(QueryMsg), &out_dir);
-    export_schema(&schema_for!(PaymentState), &out_dir);
-    export_schema(&schema_for!(PaymentsResponse), &out_dir);
+    export_all(&out_dir);
+}
+
+fn export_all(out_dir: &Path) {
+    create_dir_all(out_dir).unwrap();
+    remove_schemas(out_dir).unwrap();
+
+    export_schema(&schema_for!(InstantiateMsg), out_dir);
+    export_schema(&schema_for!(ExecuteMsg), out_dir);
+    export_schema(&schema_for!(QueryMsg), out_dir);
+    export_schema(&schema_for!(MigrateMsg), out_dir);
+    export_schema(&schema_for!(PaymentState), out_dir);
+    export_schema(&schema_for!(PaymentsResponse), out_dir);
+    export_schema(&schema_for!(ConfigResponse), out_dir);
+    export_schema(&schema_for!(ClaimableResponse), out_dir);
+    export_schema(&schema_for!(StopPaymentResponse), out_dir);
+    export_schema(&schema_for!(AddPaymentsResponse), out_dir);
+    export_schema(&schema_for!(NextUnlockResponse), out_dir);
+    export_schema(&schema_for!(PaymentsCountResponse), out_dir);
+    export_schema(&schema_for!(DueCountResponse), out_dir);
+    export_schema(&schema_for!(SimulatePayResponse), out_dir);
+    export_schema(&schema_for!(RecipientSummaryResponse), out_dir);
+    export_schema(&schema_for!(BalancesResponse), out_dir);
+    export_schema(&schema_for!(GroupedPaymentsResponse), out_dir);
+    export_schema(&schema_for!(ProgressResponse), out_dir);
+    export_schema(&schema_for!(StateResponse), out_dir);
+    export_schema(&schema_for!(OutstandingByRecipientResponse), out_dir);
+    export_schema(&schema_for!(RecipientsResponse), out_dir);
+    export_schema(&schema_for!(StatsResponse), out_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::fs::{read_dir, read_to_string, remove_dir_all};
+
+    fn schemas(dir: &Path) -> BTreeMap<String, String> {
+        read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                (name, read_to_string(&path).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn schema_is_current() {
+        // Per process, so concurrent runs don't export into each other's dir
+        let out_dir =
+            std::env::temp_dir().join(format!("cw-vesting-schema-{}", std::process::id()));
+        export_all(&out_dir);
+        let generated = schemas(&out_dir);
+        remove_dir_all(&out_dir).unwrap();
+        assert!(!generated.is_empty());
+        for (name, schema) in &generated {
+            assert!(schema.contains("\"title\""), "{} is empty", name);
+        }
+
+        // The checked in files must match one for one, rerun the example if
+        // this fails
+        let checked_in = schemas(&Path::new(env!("CARGO_MANIFEST_DIR")).join("schema"));
+        assert_eq!(generated, checked_in);
+    }
 }
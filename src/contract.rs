@@ -1,810 +1,9600 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order, Response,
-    StdResult, WasmMsg,
+    attr, to_binary, to_vec, Addr, Api, Attribute, Binary, BlockInfo, Coin, ContractResult,
+    CosmosMsg, Deps, DepsMut, Empty, Env, IbcMsg, IbcTimeout, MessageInfo, Order, QueryRequest,
+    Reply, Response, StdError, StdResult, Storage, SubMsg, SystemResult, Timestamp, Uint128,
+    WasmMsg, WasmQuery,
 };
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, Payment, PaymentsResponse, QueryMsg};
-use crate::state::{next_id, PaymentState, PAYMENTS};
-use cw20::Cw20ExecuteMsg;
+use crate::msg::{
+    AddPaymentsResponse, BalancesResponse, ClaimableResponse, ConfigResponse, DueCountResponse,
+    ExecuteMsg, Fee, GroupedPaymentsResponse, InstantiateMsg, MigrateMsg, NextUnlockResponse,
+    OutstandingByRecipientResponse, Payment, PaymentStatus, PaymentsCountResponse,
+    PaymentsResponse, Payout, PayoutOrder, ProgressResponse, QueryMsg, RecipientOutstanding,
+    RecipientSummaryResponse, RecipientsResponse, SimulatePayResponse, StateResponse,
+    StatsResponse, StopPaymentResponse, Totals, Update,
+};
+use crate::state::{
+    decrement_count, increment_count, migrate_payment_count, migrate_payment_indexes,
+    migrate_status_counts, next_id, payments, Config, Cw20Delivery, PaymentState, PendingStop,
+    Stats, APPROVALS, CONFIG, LAST_SWEPT_ID, PAID_COUNT, PAYMENT_COUNT, PAYOUT_ADDRESSES,
+    PAYOUT_DELIVERY, PAYOUT_FEE, PAYOUT_ROLLBACK, PROCESSED_BATCHES, SEEN_RECIPIENTS, STATS,
+    STOPPED_COUNT,
+};
+use cw0::{Duration, Expiration};
+use cw2::set_contract_version;
+use cw20::{BalanceResponse, Cw20CoinVerified, Cw20ExecuteMsg, Cw20QueryMsg, TokenInfoResponse};
+use cw_storage_plus::{Bound, U8Key};
+use std::cmp::Reverse;
+
+// version info for migration info
+const CONTRACT_NAME: &str = env!("CARGO_PKG_NAME");
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const MAX_MEMO_LENGTH: usize = 256;
+// Most payments a single instantiate or AddPayments can store
+pub const MAX_SCHEDULE_LEN: usize = 500;
+// Page size of paginated queries
+const DEFAULT_LIMIT: u32 = 30;
+const MAX_LIMIT: u32 = 100;
+// Failed payouts in a row after which a payment is paused for an admin to look at
+pub const MAX_PAYOUT_FAILURES: u32 = 3;
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
-    deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
-    for p in msg.schedule.into_iter() {
-        let id = next_id(deps.storage)?;
-        PAYMENTS.save(
-            deps.storage,
-            id.into(),
-            &PaymentState {
-                payment: p,
-                paid: false,
-                id,
-            },
-        )?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    let admin = validate_admin(deps.api, &env, &msg.admin)?;
+    for denom in msg
+        .default_denom
+        .iter()
+        .chain(msg.allowed_denoms.iter().flatten())
+    {
+        validate_denom(denom)?;
     }
-    Ok(Response::new().add_attribute("method", "instantiate"))
-    //.add_attribute("count", msg.schedule))
-}
+    CONFIG.save(
+        deps.storage,
+        &Config {
+            enforce_order: msg.enforce_order,
+            default_denom: msg.default_denom,
+            allowed_denoms: msg.allowed_denoms,
+            allow_never: msg.allow_never.unwrap_or(false),
+            ..Config::new(info.sender.clone(), admin)
+        },
+    )?;
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn execute(
-    deps: DepsMut,
-    env: Env,
-    _info: MessageInfo,
-    msg: ExecuteMsg,
-) -> Result<Response, ContractError> {
-    match msg {
-        ExecuteMsg::Pay {} => execute_pay(deps, env),
+    if let Some(expected) = msg.expected_cw20_total {
+        check_cw20_supply(deps.as_ref(), &msg.schedule, expected)?;
     }
-}
+    let ids = save_payments(deps.branch(), &env, msg.schedule)?;
 
-pub fn execute_pay(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
-    let to_be_paid: Vec<PaymentState> = PAYMENTS
-        .range(deps.storage, None, None, Order::Ascending)
-        .filter_map(|r| match r {
-            Ok(r) => Some(r.1),
-            _ => None,
-        })
-        .filter(|p| !p.paid && p.payment.time.is_expired(&env.block))
-        .collect();
+    let mut res = Response::new()
+        .add_attribute("method", "instantiate")
+        .add_attribute("owner", info.sender.to_string())
+        .add_attribute("num_payments", ids.len().to_string());
+    // Totals per denom and token rather than per payment, so large schedules
+    // don't blow up the event
+    let committed = query_outstanding(deps.as_ref())?;
+    if !committed.native.is_empty() {
+        let native: Vec<String> = committed.native.iter().map(|c| c.to_string()).collect();
+        res = res.add_attribute("committed", native.join(","));
+    }
+    if !committed.cw20.is_empty() {
+        let cw20: Vec<String> = committed
+            .cw20
+            .iter()
+            .map(|c| format!("{}{}", c.amount, c.address))
+            .collect();
+        res = res.add_attribute("committed_cw20", cw20.join(","));
+    }
 
-    // Get cosmos payment messages
-    let payment_msgs: Vec<CosmosMsg> = to_be_paid
-        .clone()
-        .into_iter()
-        .map(|p| get_payment_message(&p.payment))
-        .collect::<StdResult<Vec<CosmosMsg>>>()?;
-
-    // Update payments to paid
-    for p in to_be_paid.into_iter() {
-        PAYMENTS.update(deps.storage, p.id.into(), |p| match p {
-            Some(p) => Ok(PaymentState { paid: true, ..p }),
-            None => Err(ContractError::PaymentNotFound {}),
-        })?;
+    // Record any funds sent along with instantiation
+    if !info.funds.is_empty() {
+        let funded: Vec<String> = info.funds.iter().map(|c| c.to_string()).collect();
+        res = res.add_attribute("funded", funded.join(","));
     }
 
-    Ok(Response::new().add_messages(payment_msgs))
-    //.add_attribute("paid", to_be_paid))
+    Ok(res)
 }
 
-pub fn get_payment_message(p: &Payment) -> StdResult<CosmosMsg> {
-    match p.token_address {
-        Some(_) => get_token_payment(p),
-        None => get_native_payment(p),
+// The contract has no keys, so neither it nor an empty address can act as admin
+fn validate_admin(api: &dyn Api, env: &Env, address: &Addr) -> Result<Addr, ContractError> {
+    if address.as_str().is_empty() || *address == env.contract.address {
+        return Err(ContractError::InvalidOwner {});
     }
+    Ok(api.addr_validate(address.as_str())?)
 }
 
-pub fn get_token_payment(p: &Payment) -> StdResult<CosmosMsg> {
-    let transfer_cw20_msg = Cw20ExecuteMsg::Transfer {
-        recipient: p.recipient.to_string(),
-        amount: p.amount,
-    };
+// Queries every distinct token in the schedule, which also checks it exists
+fn check_cw20_supply(
+    deps: Deps,
+    schedule: &[Payment],
+    expected: Uint128,
+) -> Result<(), ContractError> {
+    let mut checked: Vec<&Addr> = vec![];
+    for token in schedule.iter().filter_map(|p| p.token_address.as_ref()) {
+        if checked.contains(&token) {
+            continue;
+        }
+        let info: TokenInfoResponse = deps
+            .querier
+            .query_wasm_smart(token, &Cw20QueryMsg::TokenInfo {})?;
+        if info.total_supply < expected {
+            return Err(ContractError::Cw20SupplyTooLow {
+                token: token.to_string(),
+                supply: info.total_supply,
+                expected,
+            });
+        }
+        checked.push(token);
+    }
+    Ok(())
+}
 
-    let exec_cw20_transfer = WasmMsg::Execute {
-        contract_addr: p.token_address.clone().unwrap().to_string(),
-        msg: to_binary(&transfer_cw20_msg)?,
-        funds: vec![],
+// Validates and stores new payments, returning their ids
+fn save_payments(
+    deps: DepsMut,
+    env: &Env,
+    schedule: Vec<Payment>,
+) -> Result<Vec<u64>, ContractError> {
+    if schedule.len() > MAX_SCHEDULE_LEN {
+        return Err(ContractError::TooManyPayments {
+            max: MAX_SCHEDULE_LEN,
+        });
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let mut last_time = match config.enforce_order {
+        true => payments()
+            .range(deps.storage, None, None, Order::Descending)
+            .next()
+            .transpose()?
+            .map(|(_, p)| p.payment.time),
+        false => None,
     };
 
-    Ok(exec_cw20_transfer.into())
+    let mut ids = vec![];
+    for p in schedule.into_iter() {
+        let p = resolve_payment(&config, &env.block, p)?;
+        validate_payment(deps.api, &p)?;
+        // Milestones unlock at their own times, which need the same check
+        let never = std::iter::once(&p.time)
+            .chain(p.milestones.iter().flatten().map(|m| &m.time))
+            .any(|time| *time == Expiration::Never {});
+        if never && !config.allow_never {
+            return Err(ContractError::NeverExpires {});
+        }
+        if let Some(allowed) = &config.allowed_denoms {
+            let mut denoms = p
+                .token_address
+                .is_none()
+                .then_some(&p.denom)
+                .into_iter()
+                .chain(p.extra_coins.iter().flatten().map(|c| &c.denom));
+            if denoms.any(|d| !allowed.contains(d)) {
+                return Err(ContractError::DenomNotAllowed {});
+            }
+        }
+        if config.enforce_order {
+            if let Some(last) = last_time {
+                if !in_order(&last, &p.time) {
+                    return Err(ContractError::OutOfOrder {});
+                }
+            }
+            last_time = Some(p.time);
+        }
+        let id = next_id(deps.storage)?;
+        let state = PaymentState {
+            payment: p,
+            paid: false,
+            id,
+            installments_paid: 0,
+            stopped: false,
+            paused: false,
+            paid_at: None,
+            stopping: None,
+            fail_count: 0,
+        };
+        payments().save(deps.storage, id.into(), &state)?;
+        record_scheduled(deps.storage, &state)?;
+        ids.push(id);
+    }
+    Ok(ids)
 }
 
-pub fn get_native_payment(p: &Payment) -> StdResult<CosmosMsg> {
-    let transfer_bank_msg = cosmwasm_std::BankMsg::Send {
-        to_address: p.recipient.clone().into_string(),
-        amount: vec![Coin {
-            denom: p.denom.clone(),
-            amount: p.amount,
-        }],
-    };
+// Fills in the default denom for native payments without one and turns a time
+// offset into an absolute time
+fn resolve_payment(
+    config: &Config,
+    block: &BlockInfo,
+    mut p: Payment,
+) -> Result<Payment, ContractError> {
+    if p.token_address.is_none() && p.denom.is_empty() {
+        p.denom = config
+            .default_denom
+            .clone()
+            .ok_or(ContractError::MissingDenom {})?;
+    }
+    if let Some(offset) = p.time_offset.take() {
+        if p.milestones.is_some() {
+            return Err(ContractError::InvalidMilestones {});
+        }
+        p.time = offset.after(block);
+    }
+    Ok(p)
+}
 
-    Ok(transfer_bank_msg.into())
+fn validate_payment(api: &dyn Api, p: &Payment) -> Result<(), ContractError> {
+    // Remote recipients use the other chain's address format
+    match &p.ibc_channel {
+        Some(_) => {
+            if p.token_address.is_some()
+                || p.extra_coins.is_some()
+                || p.on_receive.is_some()
+                || p.recipients.is_some()
+            {
+                return Err(ContractError::InvalidIbcPayment {});
+            }
+        }
+        None => {
+            api.addr_validate(p.recipient.as_str())?;
+        }
+    }
+    if let Some(condition) = &p.condition {
+        api.addr_validate(condition.contract.as_str())?;
+    }
+    match &p.token_address {
+        Some(address) => {
+            api.addr_validate(address.as_str())?;
+            if !p.denom.is_empty() {
+                return Err(ContractError::DenomWithToken {});
+            }
+        }
+        None => validate_denom(&p.denom)?,
+    }
+    if let Some(memo) = &p.memo {
+        if memo.chars().count() > MAX_MEMO_LENGTH {
+            return Err(ContractError::MemoTooLong {
+                max: MAX_MEMO_LENGTH,
+            });
+        }
+    }
+    if let Some(r) = &p.recurrence {
+        let valid_interval = match (p.time, r.interval) {
+            (Expiration::AtHeight(_), Duration::Height(interval)) => interval > 0,
+            (Expiration::AtTime(_), Duration::Time(interval)) => interval > 0,
+            _ => false,
+        };
+        if !valid_interval || r.installments == 0 {
+            return Err(ContractError::InvalidRecurrence {});
+        }
+    }
+    if let Some(milestones) = &p.milestones {
+        let ordered = milestones
+            .windows(2)
+            .all(|w| matches!(w[0].time.partial_cmp(&w[1].time), Some(o) if o.is_le()));
+        let total: u64 = milestones.iter().map(|m| m.percent as u64).sum();
+        if p.recurrence.is_some()
+            || milestones.first().map(|m| m.time) != Some(p.time)
+            || !ordered
+            || total != 100
+        {
+            return Err(ContractError::InvalidMilestones {});
+        }
+    }
+    if let Some(extra) = &p.extra_coins {
+        let mut denoms = vec![&p.denom];
+        for c in extra {
+            validate_denom(&c.denom)?;
+            if denoms.contains(&&c.denom) {
+                return Err(ContractError::InvalidExtraCoins {});
+            }
+            denoms.push(&c.denom);
+        }
+        if p.token_address.is_some() {
+            return Err(ContractError::InvalidExtraCoins {});
+        }
+    }
+    if let Some(recipients) = &p.recipients {
+        let total: u32 = recipients.iter().map(|(_, w)| *w as u32).sum();
+        if total != 10000 || recipients.iter().any(|(_, w)| *w == 0) || p.on_receive.is_some() {
+            return Err(ContractError::InvalidSplit {});
+        }
+        for (addr, _) in recipients {
+            api.addr_validate(addr.as_str())?;
+        }
+    }
+    Ok(())
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetPayments {} => to_binary(&query_payments(deps)),
+// Cosmos SDK denom rules: a letter then 2 to 127 letters, digits or /:._-. Plain
+// denoms must also be lowercase so "uJuno" isn't counted apart from "ujuno",
+// while path denoms like ibc/<HASH> keep their case
+fn validate_denom(denom: &str) -> Result<(), ContractError> {
+    let valid_chars = denom
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/:._-".contains(c));
+    let starts_with_letter = denom.starts_with(|c: char| c.is_ascii_alphabetic());
+    let lowercase = denom.contains('/') || !denom.chars().any(|c| c.is_ascii_uppercase());
+    if !(3..=128).contains(&denom.len()) || !valid_chars || !starts_with_letter || !lowercase {
+        return Err(ContractError::InvalidDenom {});
     }
+    Ok(())
 }
 
-fn query_payments(deps: Deps) -> PaymentsResponse {
-    PaymentsResponse {
-        payments: PAYMENTS
-            .range(deps.storage, None, None, Order::Ascending)
-            .filter_map(|p| match p {
-                Ok(p) => Some(p.1),
-                Err(_) => None,
-            })
-            .collect(),
+fn total_installments(p: &Payment) -> u32 {
+    match (&p.recurrence, &p.milestones) {
+        (Some(r), _) => r.installments,
+        (_, Some(milestones)) => milestones.len() as u32,
+        _ => 1,
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info, MockApi, MockStorage};
-    use cosmwasm_std::{coin, coins, from_binary, Addr, Empty, Uint128};
-    use cw0::Expiration;
-    use cw20::{Cw20Coin, Cw20Contract};
-    use cw_multi_test::{
-        next_block, App, AppResponse, BankKeeper, Contract, ContractWrapper, Executor,
+// Number of installments unlocked at the given block
+fn matured_installments(p: &Payment, block: &BlockInfo) -> u32 {
+    if !p.time.is_expired(block) {
+        return 0;
+    }
+    if let Some(milestones) = &p.milestones {
+        // Milestones are ordered so the matured ones are a prefix
+        return milestones
+            .iter()
+            .take_while(|m| m.time.is_expired(block))
+            .count() as u32;
+    }
+    let r = match &p.recurrence {
+        Some(r) => r,
+        None => return 1,
     };
-    use std::borrow::Cow::Owned;
-    use std::fmt::Error;
-
-    const OWNER: &str = "owner0001";
-    const FUNDER: &str = "funder";
-    const PAYEE2: &str = "payee0002";
-    const PAYEE3: &str = "payee0003";
-
-    const NATIVE_TOKEN_DENOM: &str = "ujuno";
-    const INITIAL_BALANCE: u128 = 2000000;
+    let periods = match (p.time, r.interval) {
+        (Expiration::AtHeight(start), Duration::Height(interval)) => {
+            (block.height - start) / interval
+        }
+        (Expiration::AtTime(start), Duration::Time(interval)) => {
+            (block.time.seconds() - start.seconds()) / interval
+        }
+        _ => 0,
+    };
+    periods.saturating_add(1).min(r.installments as u64) as u32
+}
 
-    pub fn contract_vest() -> Box<dyn Contract<Empty>> {
-        let contract = ContractWrapper::new(
-            crate::contract::execute,
-            crate::contract::instantiate,
-            crate::contract::query,
-        );
-        Box::new(contract)
+// Total of an installment amount released by the first installments of a
+// payment. Milestones use cumulative percentages so the last one releases any
+// rounding dust
+fn unlocked_amount(p: &Payment, amount: Uint128, installments: u32) -> StdResult<Uint128> {
+    match &p.milestones {
+        Some(milestones) => {
+            let percent: u64 = milestones
+                .iter()
+                .take(installments as usize)
+                .map(|m| m.percent as u64)
+                .sum();
+            Ok(amount.multiply_ratio(percent, 100u64))
+        }
+        None => Ok(amount.checked_mul(Uint128::from(installments))?),
     }
+}
 
-    pub fn contract_cw20() -> Box<dyn Contract<Empty>> {
-        let contract = ContractWrapper::new(
-            cw20_base::contract::execute,
-            cw20_base::contract::instantiate,
-            cw20_base::contract::query,
-        );
-        Box::new(contract)
+// The payment with its amounts set to what installments from..to release
+fn released(p: &Payment, from: u32, to: u32) -> StdResult<Payment> {
+    let release = |amount| -> StdResult<Uint128> {
+        Ok(unlocked_amount(p, amount, to)?.checked_sub(unlocked_amount(p, amount, from)?)?)
+    };
+    let extra_coins = match &p.extra_coins {
+        Some(extra) => Some(
+            extra
+                .iter()
+                .map(|c| {
+                    Ok(Coin {
+                        denom: c.denom.clone(),
+                        amount: release(c.amount)?,
+                    })
+                })
+                .collect::<StdResult<Vec<Coin>>>()?,
+        ),
+        None => None,
+    };
+    Ok(Payment {
+        amount: release(p.amount)?,
+        extra_coins,
+        ..p.clone()
+    })
+}
+
+// Unlocked but not yet paid out at the given block
+fn due_payment(p: &PaymentState, block: &BlockInfo) -> StdResult<Payment> {
+    if p.paid || p.stopped {
+        return released(&p.payment, 0, 0);
     }
+    let matured = matured_installments(&p.payment, block).max(p.installments_paid);
+    released(&p.payment, p.installments_paid, matured)
+}
 
-    fn mock_app() -> App {
-        let env = mock_env();
-        let api = MockApi::default();
-        let bank = BankKeeper::new();
+fn due_amount(p: &PaymentState, block: &BlockInfo) -> StdResult<Uint128> {
+    Ok(due_payment(p, block)?.amount)
+}
 
-        App::new(api, env.block, bank, MockStorage::new())
+// Not yet paid out, whether due or not
+fn remaining_payment(p: &PaymentState) -> StdResult<Payment> {
+    if p.paid || p.stopped {
+        return released(&p.payment, 0, 0);
     }
+    released(
+        &p.payment,
+        p.installments_paid,
+        total_installments(&p.payment),
+    )
+}
 
-    // uploads code and returns address of cw20 contract
-    fn instantiate_cw20(app: &mut App) -> Addr {
-        let cw20_id = app.store_code(contract_cw20());
-        let msg = cw20_base::msg::InstantiateMsg {
-            name: String::from("Test"),
-            symbol: String::from("TEST"),
-            decimals: 6,
-            initial_balances: vec![
-                Cw20Coin {
-                    address: OWNER.to_string(),
-                    amount: Uint128::new(INITIAL_BALANCE),
-                },
-                Cw20Coin {
-                    address: FUNDER.to_string(),
-                    amount: Uint128::new(INITIAL_BALANCE),
-                },
-                Cw20Coin {
-                    address: PAYEE2.to_string(),
-                    amount: Uint128::new(INITIAL_BALANCE),
-                },
-                Cw20Coin {
-                    address: PAYEE3.to_string(),
-                    amount: Uint128::new(INITIAL_BALANCE * 2),
-                },
-            ],
-            mint: None,
-            marketing: None,
-        };
-        app.instantiate_contract(cw20_id, Addr::unchecked(OWNER), &msg, &[], "cw20", None)
-            .unwrap()
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    if CONFIG.load(deps.storage)?.terminated {
+        return Err(ContractError::Terminated {});
+    }
+    match msg {
+        ExecuteMsg::Terminate {} => execute_terminate(deps, env, info),
+        ExecuteMsg::RescueToken {
+            token_address,
+            denom,
+            to,
+        } => execute_rescue_token(deps, env, info, token_address, denom, to),
+        ExecuteMsg::DelayPayment { id, new_time } => {
+            execute_delay_payment(deps, env, info, id, new_time)
+        }
+        ExecuteMsg::SplitPayment { id, amount, time } => {
+            execute_split_payment(deps, env, info, id, amount, time)
+        }
+        ExecuteMsg::TopUpPayment { id, additional } => {
+            execute_top_up_payment(deps, env, info, id, additional)
+        }
+        ExecuteMsg::Approve { id } => execute_approve(deps, info, id),
+        ExecuteMsg::SetPayoutAddress { address } => execute_set_payout_address(deps, info, address),
+        ExecuteMsg::Pay { limit } => execute_pay(deps, env, info, limit),
+        ExecuteMsg::ClaimAll {} => execute_claim_all(deps, env, info),
+        ExecuteMsg::Fund { then_pay } => execute_fund(deps, env, info, then_pay),
+        ExecuteMsg::AddAdmin { address } => execute_add_admin(deps, env, info, address),
+        ExecuteMsg::RemoveAdmin { address } => execute_remove_admin(deps, info, address),
+        ExecuteMsg::AddPayments {
+            schedule,
+            dedupe,
+            batch_id,
+            auto_pay,
+        } => execute_add_payments(
+            deps,
+            env,
+            info,
+            schedule,
+            dedupe.unwrap_or(false),
+            batch_id,
+            auto_pay.unwrap_or(false),
+        ),
+        ExecuteMsg::AddLinearSchedule {
+            recipient,
+            total,
+            denom,
+            token_address,
+            start,
+            end,
+            num_installments,
+        } => {
+            let schedule = linear_schedule(
+                recipient,
+                total,
+                denom,
+                token_address,
+                start,
+                end,
+                num_installments,
+            )?;
+            execute_add_payments(deps, env, info, schedule, false, None, false)
+        }
+        ExecuteMsg::UpdateConfig {
+            owner,
+            enabled,
+            payout_start,
+            schedule_locked,
+            payers,
+            ibc_timeout_seconds,
+            fee,
+            min_payout,
+            stop_grace,
+            payout_order,
+            keeper_reward,
+            expected_version,
+        } => execute_update_config(
+            deps,
+            env,
+            info,
+            owner,
+            enabled,
+            payout_start,
+            schedule_locked,
+            payers,
+            ibc_timeout_seconds,
+            fee,
+            min_payout,
+            stop_grace,
+            payout_order,
+            keeper_reward,
+            expected_version,
+        ),
+        ExecuteMsg::StopPayment { id, refund } => {
+            execute_stop_payment(deps, env, info, id, refund.unwrap_or(true))
+        }
+        ExecuteMsg::FinalizeStop { id } => execute_finalize_stop(deps, env, info, id),
+        ExecuteMsg::StopGrant { grant_id, refund } => {
+            execute_stop_grant(deps, env, info, grant_id, refund.unwrap_or(true))
+        }
+        ExecuteMsg::Clawback { id } => execute_clawback(deps, env, info, id),
+        ExecuteMsg::Revoke { id } => execute_revoke(deps, env, info, id),
+        ExecuteMsg::ReplaceSchedule { schedule } => {
+            execute_replace_schedule(deps, env, info, schedule)
+        }
+        ExecuteMsg::PausePayment { id } => execute_set_paused(deps, info, id, true),
+        ExecuteMsg::ResumePayment { id } => execute_set_paused(deps, info, id, false),
     }
+}
 
-    fn instantiate_vest(app: &mut App, payments: Vec<Payment>) -> Addr {
-        let flex_id = app.store_code(contract_vest());
-        let msg = crate::msg::InstantiateMsg { schedule: payments };
-        app.instantiate_contract(flex_id, Addr::unchecked(OWNER), &msg, &[], "flex", None)
-            .unwrap()
+fn linear_schedule(
+    recipient: Addr,
+    total: Uint128,
+    denom: String,
+    token_address: Option<Addr>,
+    start: Expiration,
+    end: Expiration,
+    num_installments: u32,
+) -> Result<Vec<Payment>, ContractError> {
+    let (from, to) = match (start, end) {
+        (Expiration::AtHeight(from), Expiration::AtHeight(to)) => (from, to),
+        (Expiration::AtTime(from), Expiration::AtTime(to)) => (from.nanos(), to.nanos()),
+        _ => return Err(ContractError::InvalidLinearSchedule {}),
+    };
+    // Checked before building so a huge count can't run out of gas allocating
+    if num_installments as usize > MAX_SCHEDULE_LEN {
+        return Err(ContractError::TooManyPayments {
+            max: MAX_SCHEDULE_LEN,
+        });
+    }
+    let n = num_installments as u128;
+    if n == 0 || to < from || total.u128() < n {
+        return Err(ContractError::InvalidLinearSchedule {});
     }
 
-    fn get_accounts() -> (Addr, Addr, Addr, Addr) {
-        let owner: Addr = Addr::unchecked(OWNER);
-        let funder: Addr = Addr::unchecked(FUNDER);
-        let voter2: Addr = Addr::unchecked(PAYEE2);
-        let voter3: Addr = Addr::unchecked(PAYEE3);
+    let amount = total.u128() / n;
+    let span = (to - from) as u128;
+    let schedule = (0..n)
+        .map(|i| {
+            let offset = match n {
+                1 => 0,
+                _ => (span * i / (n - 1)) as u64,
+            };
+            let time = match start {
+                Expiration::AtHeight(_) => Expiration::AtHeight(from + offset),
+                _ => Expiration::AtTime(Timestamp::from_nanos(from + offset)),
+            };
+            // Dust from the even split goes to the last installment
+            let amount = match i == n - 1 {
+                true => total.u128() - amount * (n - 1),
+                false => amount,
+            };
+            Payment {
+                recipient: recipient.clone(),
+                amount: Uint128::new(amount),
+                denom: denom.clone(),
+                token_address: token_address.clone(),
+                time,
+                time_offset: None,
+                recurrence: None,
+                milestones: None,
+                extra_coins: None,
+                ibc_channel: None,
+                expiry: None,
+                active_from: None,
+                memo: None,
+                on_receive: None,
+                grant_id: None,
+                recipients: None,
+                condition: None,
+                require_approval: false,
+            }
+        })
+        .collect();
+    Ok(schedule)
+}
 
-        (owner, funder, voter2, voter3)
+pub fn execute_add_payments(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    schedule: Vec<Payment>,
+    dedupe: bool,
+    batch_id: Option<String>,
+    auto_pay: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
     }
-
-    fn fund_vest_contract(app: &mut App, vest: Addr, cw20: Addr, funder: Addr, amount: Uint128) {
-        app.execute_contract(
-            funder,
-            cw20,
-            &Cw20ExecuteMsg::Transfer {
-                recipient: vest.to_string(),
-                amount,
-            },
-            &vec![],
-        );
+    if config.schedule_locked {
+        return Err(ContractError::ScheduleLocked {});
+    }
+    if let Some(batch_id) = &batch_id {
+        if PROCESSED_BATCHES.has(deps.storage, batch_id) {
+            return Err(ContractError::DuplicateBatch {});
+        }
+        PROCESSED_BATCHES.save(deps.storage, batch_id, &())?;
     }
 
-    #[test]
-    fn proper_initialization() {
-        let mut deps = mock_dependencies(&[]);
+    // Compare against stored payments with their defaults filled in
+    let mut schedule = schedule
+        .into_iter()
+        .map(|p| resolve_payment(&config, &env.block, p))
+        .collect::<Result<Vec<Payment>, ContractError>>()?;
 
-        let msg = InstantiateMsg { schedule: vec![] };
-        let info = mock_info("creator", &coins(1000, "earth"));
+    let mut skipped = 0;
+    if dedupe {
+        let active: Vec<Payment> = query_payments(deps.as_ref())
+            .payments
+            .into_iter()
+            .filter(|p| !p.paid && !p.stopped)
+            .map(|p| p.payment)
+            .collect();
+        let before = schedule.len();
+        schedule.retain(|p| !active.iter().any(|a| is_duplicate(a, p)));
+        skipped = (before - schedule.len()) as u32;
+    }
 
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+    let ids = save_payments(deps.branch(), &env, schedule)?;
 
-        // it worked, let's query the state
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetPayments {}).unwrap();
-        let value: PaymentsResponse = from_binary(&res).unwrap();
-        assert_eq!(0, value.payments.len());
+    let mut res = Response::new()
+        .add_attribute("method", "add_payments")
+        .add_attribute("count", ids.len().to_string());
+    if dedupe {
+        res = res.add_attribute("skipped", skipped.to_string());
+    }
+    if let Some(batch_id) = batch_id {
+        res = res.add_attribute("batch_id", batch_id);
     }
 
-    #[test]
-    fn get_payments() {
-        let mut deps = mock_dependencies(&[]);
+    let started = config.payout_start.is_none_or(|s| s.is_expired(&env.block));
+    if auto_pay && started && config.enabled {
+        let selection = select_payouts(deps.as_ref(), &env, None, Some(&ids))?;
+        res = send_payouts(deps, &env, &config, selection, res)?;
+    }
+    Ok(res.set_data(to_binary(&AddPaymentsResponse { ids, skipped })?))
+}
 
-        let payment = Payment {
-            recipient: Addr::unchecked(String::from("test")),
-            amount: Uint128::new(1),
-            denom: "".to_string(),
-            token_address: None,
-            time: Expiration::AtHeight(1),
-        };
-        let payment2 = payment.clone();
-        let msg = InstantiateMsg {
-            schedule: vec![payment.clone(), payment2],
-        };
-        let info = mock_info("creator", &coins(1000, "earth"));
+fn is_duplicate(a: &Payment, b: &Payment) -> bool {
+    a.recipient == b.recipient
+        && a.amount == b.amount
+        && a.denom == b.denom
+        && a.token_address == b.token_address
+        && a.time == b.time
+}
 
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+// Mixed height and time unlocks cannot be ordered
+fn in_order(earlier: &Expiration, later: &Expiration) -> bool {
+    matches!(earlier.partial_cmp(later), Some(o) if o.is_le())
+}
 
-        // it worked, let's query the state
-        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetPayments {}).unwrap();
-        let value: PaymentsResponse = from_binary(&res).unwrap();
-        assert_eq!(2, value.payments.len());
+// Every id based handler reports missing and removed ids the same way
+fn load_payment(storage: &dyn Storage, id: u64) -> Result<PaymentState, ContractError> {
+    payments()
+        .may_load(storage, id.into())?
+        .ok_or(ContractError::PaymentNotFound { id })
+}
+
+pub fn execute_stop_payment(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    refund: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
     }
 
-    #[test]
-    fn proper_initialization_integration() {
-        let mut app = mock_app();
+    let payment = load_payment(deps.storage, id)?;
+    check_stoppable(&payment, &env.block)?;
+    if let Some(grace) = config.stop_grace {
+        let effective_at = begin_stop(deps.storage, &env, &payment, grace, refund)?;
+        return Ok(Response::new()
+            .add_attribute("method", "stop_payment")
+            .add_attribute("id", id.to_string())
+            .add_attribute("effective_at", effective_at.to_string()));
+    }
+    stop_payment(deps.branch(), &env, &payment, refund)?;
+    finish_stop("stop_payment", &config, &env, payment, refund)
+}
 
-        let (owner, funder, _payee2, _payee3) = get_accounts();
+pub fn execute_finalize_stop(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
 
-        let cw20_addr = instantiate_cw20(&mut app);
-        let cw20 = Cw20Contract(cw20_addr.clone());
+    let payment = load_payment(deps.storage, id)?;
+    let pending = match (&payment.stopping, payment.stopped) {
+        (Some(pending), false) => pending.clone(),
+        _ => return Err(ContractError::NotStopping {}),
+    };
+    if !pending.effective_at.is_expired(&env.block) {
+        return Err(ContractError::GraceNotElapsed {});
+    }
+    stop_payment(deps.branch(), &env, &payment, pending.refund)?;
+    finish_stop("finalize_stop", &config, &env, payment, pending.refund)
+}
 
-        let payments = vec![Payment {
-            recipient: owner,
-            amount: Uint128::new(1),
-            denom: cw20_addr.to_string(),
-            token_address: None,
-            time: Default::default(),
-        }];
+// A payment past its active_from can only end through Terminate or Clawback,
+// every other way of stopping it checks this first
+fn check_stoppable(payment: &PaymentState, block: &BlockInfo) -> Result<(), ContractError> {
+    match &payment.payment.active_from {
+        Some(active_from) if active_from.is_expired(block) => Err(ContractError::PaymentActive {}),
+        _ => Ok(()),
+    }
+}
 
-        let vest_addr = instantiate_vest(&mut app, payments);
+// Starts the stop grace period of an active payment, returning when it ends
+fn begin_stop(
+    storage: &mut dyn Storage,
+    env: &Env,
+    payment: &PaymentState,
+    grace: Duration,
+    refund: bool,
+) -> Result<Expiration, ContractError> {
+    if payment.paid || payment.stopped || payment.stopping.is_some() {
+        return Err(ContractError::PaymentNotActive {});
     }
+    let effective_at = grace.after(&env.block);
+    payments().save(
+        storage,
+        payment.id.into(),
+        &PaymentState {
+            stopping: Some(PendingStop {
+                effective_at,
+                refund,
+            }),
+            ..payment.clone()
+        },
+    )?;
+    Ok(effective_at)
+}
 
-    #[test]
-    fn single_cw20_payment() {
-        let mut app = mock_app();
+// Response for a payment that was just stopped, refunding it when asked
+fn finish_stop(
+    method: &str,
+    config: &Config,
+    env: &Env,
+    payment: PaymentState,
+    refund: bool,
+) -> Result<Response, ContractError> {
+    let id = payment.id;
+    let mut res = Response::new()
+        .add_attribute("method", method)
+        .add_attribute("id", id.to_string());
+    if let Some(memo) = &payment.payment.memo {
+        res = res.add_attribute("memo", memo);
+    }
 
-        let (owner, funder, _payee2, _payee3) = get_accounts();
+    // Unpaid installments go back to the owner
+    let mut refunded = Uint128::zero();
+    if refund {
+        let remaining = remaining_payment(&payment)?;
+        refunded = remaining.amount;
+        res = res
+            .add_attributes([refund_attribute(id, "stop")])
+            .add_message(get_payment_message(&remaining, &config.owner, env, config)?);
+    }
 
-        let cw20_addr = instantiate_cw20(&mut app);
-        let cw20 = Cw20Contract(cw20_addr.clone());
+    Ok(res.set_data(to_binary(&StopPaymentResponse {
+        id,
+        refunded,
+        denom: payment.payment.denom,
+        token_address: payment.payment.token_address,
+    })?))
+}
 
-        let payments = vec![Payment {
-            recipient: owner.clone(),
-            amount: Uint128::new(1),
-            denom: cw20_addr.to_string(),
-            token_address: Some(cw20_addr.clone()),
-            time: Expiration::AtHeight(1),
-        }];
+// Bank sends carry no memo, so this lets treasury software match a refund to
+// the payment it came from
+fn refund_attribute(id: u64, reason: &str) -> Attribute {
+    attr("refund", format!("payment_id={},reason={}", id, reason))
+}
 
+pub fn execute_stop_grant(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    grant_id: u64,
+    refund: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let members: Vec<PaymentState> = query_payments(deps.as_ref())
+        .payments
+        .into_iter()
+        .filter(|p| {
+            p.payment.grant_id == Some(grant_id) && !p.paid && !p.stopped && p.stopping.is_none()
+        })
+        .collect();
+    if members.is_empty() {
+        return Err(ContractError::GrantNotFound { grant_id });
+    }
+
+    let mut res = Response::new()
+        .add_attribute("method", "stop_grant")
+        .add_attribute("grant_id", grant_id.to_string())
+        .add_attribute("count", members.len().to_string());
+    let mut stopped = vec![];
+    for payment in members {
+        check_stoppable(&payment, &env.block)?;
+        if let Some(grace) = config.stop_grace {
+            begin_stop(deps.storage, &env, &payment, grace, refund)?;
+            stopped.push(StopPaymentResponse {
+                id: payment.id,
+                refunded: Uint128::zero(),
+                denom: payment.payment.denom,
+                token_address: payment.payment.token_address,
+            });
+            continue;
+        }
+        stop_payment(deps.branch(), &env, &payment, refund)?;
+
+        let mut refunded = Uint128::zero();
+        if refund {
+            let remaining = remaining_payment(&payment)?;
+            refunded = remaining.amount;
+            res = res
+                .add_attributes([refund_attribute(payment.id, "stop")])
+                .add_message(get_payment_message(
+                    &remaining,
+                    &config.owner,
+                    &env,
+                    &config,
+                )?);
+        }
+        stopped.push(StopPaymentResponse {
+            id: payment.id,
+            refunded,
+            denom: payment.payment.denom,
+            token_address: payment.payment.token_address,
+        });
+    }
+
+    Ok(res.set_data(to_binary(&stopped)?))
+}
+
+// Marks an active payment as stopped, recording whether its remainder is
+// refunded. Installments held back by a pause, missing approval or unmet
+// condition are part of the remainder
+fn stop_payment(
+    deps: DepsMut,
+    env: &Env,
+    payment: &PaymentState,
+    refund: bool,
+) -> Result<(), ContractError> {
+    if payment.paid || payment.stopped {
+        return Err(ContractError::PaymentNotActive {});
+    }
+    // Due installments Pay would send belong to the recipient even if not yet swept
+    let config = CONFIG.load(deps.storage)?;
+    if payable(deps.as_ref(), &config, payment) && !due_amount(payment, &env.block)?.is_zero() {
+        return Err(ContractError::AlreadyVested {});
+    }
+    end_payment(deps.storage, payment, refund)
+}
+
+// Stops the payment whatever is due, for callers that already decided to
+fn end_payment(
+    storage: &mut dyn Storage,
+    payment: &PaymentState,
+    refund: bool,
+) -> Result<(), ContractError> {
+    payments().save(
+        storage,
+        payment.id.into(),
+        &PaymentState {
+            stopped: true,
+            ..payment.clone()
+        },
+    )?;
+    increment_count(storage, &STOPPED_COUNT)?;
+    record_stop(storage, payment, refund)?;
+    Ok(())
+}
+
+fn update_stats(
+    storage: &mut dyn Storage,
+    update: impl FnOnce(&mut Stats) -> StdResult<()>,
+) -> StdResult<()> {
+    let mut stats = STATS.may_load(storage)?.unwrap_or_default();
+    update(&mut stats)?;
+    STATS.save(storage, &stats)
+}
+
+fn record_scheduled(storage: &mut dyn Storage, p: &PaymentState) -> StdResult<()> {
+    let remaining = remaining_payment(p)?;
+    let mut recipients = vec![&p.payment.recipient];
+    recipients.extend(p.payment.recipients.iter().flatten().map(|(addr, _)| addr));
+    let mut new_recipients = 0;
+    for recipient in recipients {
+        if !SEEN_RECIPIENTS.has(storage, recipient) {
+            SEEN_RECIPIENTS.save(storage, recipient, &())?;
+            new_recipients += 1;
+        }
+    }
+    update_stats(storage, |stats| {
+        stats.distinct_recipients += new_recipients;
+        add_payment_amount(
+            &mut stats.scheduled.native,
+            &mut stats.scheduled.cw20,
+            &remaining,
+        )?;
+        add_payment_amount(&mut stats.active.native, &mut stats.active.cw20, &remaining)
+    })
+}
+
+// Records a payout of a payment that was active, or with paid false undoes one
+fn record_payout(storage: &mut dyn Storage, payout: &Payment, paid: bool) -> StdResult<()> {
+    update_stats(storage, |stats| {
+        let (from, to) = match paid {
+            true => (&mut stats.active, &mut stats.paid),
+            false => (&mut stats.paid, &mut stats.active),
+        };
+        sub_payment_amount(&mut from.native, &mut from.cw20, payout)?;
+        add_payment_amount(&mut to.native, &mut to.cw20, payout)
+    })
+}
+
+// Call before the payment is marked stopped
+fn record_stop(storage: &mut dyn Storage, p: &PaymentState, refund: bool) -> StdResult<()> {
+    let remaining = remaining_payment(p)?;
+    update_stats(storage, |stats| {
+        sub_payment_amount(&mut stats.active.native, &mut stats.active.cw20, &remaining)?;
+        if refund {
+            add_payment_amount(
+                &mut stats.refunded.native,
+                &mut stats.refunded.cw20,
+                &remaining,
+            )?;
+        }
+        Ok(())
+    })
+}
+
+pub fn execute_replace_schedule(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    schedule: Vec<Payment>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if config.schedule_locked {
+        return Err(ContractError::ScheduleLocked {});
+    }
+
+    // Payments already being stopped are left to FinalizeStop
+    let active: Vec<PaymentState> = query_payments(deps.as_ref())
+        .payments
+        .into_iter()
+        .filter(|p| !p.paid && !p.stopped && p.stopping.is_none())
+        .collect();
+    let mut refund = Totals::default();
+    let mut refunds = vec![];
+    let mut stopping = 0;
+    for p in active.iter() {
+        check_stoppable(p, &env.block)?;
+        // As with StopPayment, the old payments keep paying out until the grace
+        // period ends and FinalizeStop refunds them
+        if let Some(grace) = config.stop_grace {
+            begin_stop(deps.storage, &env, p, grace, true)?;
+            stopping += 1;
+            continue;
+        }
+        // Due installments Pay would send belong to the recipient and must be paid first
+        if payable(deps.as_ref(), &config, p) && !due_amount(p, &env.block)?.is_zero() {
+            return Err(ContractError::AlreadyVested {});
+        }
+        add_payment_amount(&mut refund.native, &mut refund.cw20, &remaining_payment(p)?)?;
+        refunds.push(refund_attribute(p.id, "replace"));
+        record_stop(deps.storage, p, true)?;
+        if p.installments_paid > 0 {
+            payments().save(
+                deps.storage,
+                p.id.into(),
+                &PaymentState {
+                    stopped: true,
+                    ..p.clone()
+                },
+            )?;
+            increment_count(deps.storage, &STOPPED_COUNT)?;
+        } else {
+            payments().remove(deps.storage, p.id.into())?;
+        }
+    }
+
+    let ids = save_payments(deps.branch(), &env, schedule)?;
+
+    let mut res = Response::new()
+        .add_attribute("method", "replace_schedule")
+        .add_attribute("removed", (active.len() - stopping).to_string())
+        .add_attribute("count", ids.len().to_string())
+        .add_attributes(refunds);
+    if stopping > 0 {
+        res = res.add_attribute("stopping", stopping.to_string());
+    }
+    if !refund.native.is_empty() {
+        res = res.add_message(cosmwasm_std::BankMsg::Send {
+            to_address: config.owner.to_string(),
+            amount: refund.native,
+        });
+    }
+    for c in refund.cw20 {
+        res = res.add_message(WasmMsg::Execute {
+            contract_addr: c.address.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: config.owner.to_string(),
+                amount: c.amount,
+            })?,
+            funds: vec![],
+        });
+    }
+    Ok(res.set_data(to_binary(&AddPaymentsResponse { ids, skipped: 0 })?))
+}
+
+pub fn execute_set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let payment = load_payment(deps.storage, id)?;
+    if payment.paid || payment.stopped {
+        return Err(ContractError::PaymentNotActive {});
+    }
+    match (payment.paused, paused) {
+        (true, true) => return Err(ContractError::AlreadyPaused {}),
+        (false, false) => return Err(ContractError::NotPaused {}),
+        _ => {}
+    }
+    // Resuming gives a quarantined payment a fresh set of attempts
+    let state = PaymentState {
+        paused,
+        fail_count: 0,
+        ..payment
+    };
+    payments().save(deps.storage, id.into(), &state)?;
+
+    let method = if paused {
+        "pause_payment"
+    } else {
+        "resume_payment"
+    };
+    Ok(Response::new()
+        .add_attribute("method", method)
+        .add_attribute("id", id.to_string()))
+}
+
+pub fn execute_terminate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // Due installments are refunded too, as Pay may never be able to cover
+    // them and the wind-down must not depend on it
+    let payments = query_payments(deps.as_ref()).payments;
+    let mut refunds = vec![];
+    for p in payments.iter().filter(|p| !p.paid && !p.stopped) {
+        end_payment(deps.storage, p, true)?;
+        refunds.push(refund_attribute(p.id, "terminate"));
+    }
+    config.terminated = true;
+    CONFIG.save(deps.storage, &config)?;
+
+    // Stopped payments owe nothing, so everything left goes back
+    let mut res = Response::new()
+        .add_attribute("method", "terminate")
+        .add_attribute("stopped", refunds.len().to_string())
+        .add_attributes(refunds);
+    let native = deps.querier.query_all_balances(&env.contract.address)?;
+    if !native.is_empty() {
+        res = res.add_message(cosmwasm_std::BankMsg::Send {
+            to_address: config.owner.to_string(),
+            amount: native,
+        });
+    }
+    let mut tokens: Vec<&Addr> = vec![];
+    for token in payments
+        .iter()
+        .filter_map(|p| p.payment.token_address.as_ref())
+    {
+        if tokens.contains(&token) {
+            continue;
+        }
+        tokens.push(token);
+        let amount = cw20_balance(deps.as_ref(), token, &env.contract.address)?;
+        if !amount.is_zero() {
+            res = res.add_message(WasmMsg::Execute {
+                contract_addr: token.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: config.owner.to_string(),
+                    amount,
+                })?,
+                funds: vec![],
+            });
+        }
+    }
+    Ok(res)
+}
+
+pub fn execute_rescue_token(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_address: Option<Addr>,
+    denom: Option<String>,
+    to: Addr,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let to = deps.api.addr_validate(to.as_str())?;
+
+    let active: Vec<Payment> = query_payments(deps.as_ref())
+        .payments
+        .into_iter()
+        .filter(|p| !p.paid && !p.stopped)
+        .map(|p| p.payment)
+        .collect();
+    let mut res = Response::new()
+        .add_attribute("method", "rescue_token")
+        .add_attribute("to", to.to_string());
+    match (token_address, denom) {
+        (Some(token), None) => {
+            if active
+                .iter()
+                .any(|p| p.token_address.as_ref() == Some(&token))
+            {
+                return Err(ContractError::TokenCommitted {});
+            }
+            let amount = cw20_balance(deps.as_ref(), &token, &env.contract.address)?;
+            res = res
+                .add_attribute("token_address", token.to_string())
+                .add_attribute("amount", amount.to_string());
+            if !amount.is_zero() {
+                res = res.add_message(WasmMsg::Execute {
+                    contract_addr: token.to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: to.to_string(),
+                        amount,
+                    })?,
+                    funds: vec![],
+                });
+            }
+        }
+        (None, Some(denom)) => {
+            let used = active.iter().any(|p| {
+                p.token_address.is_none() && p.denom == denom
+                    || p.extra_coins.iter().flatten().any(|c| c.denom == denom)
+            });
+            if used {
+                return Err(ContractError::TokenCommitted {});
+            }
+            let balance = deps.querier.query_balance(&env.contract.address, denom)?;
+            res = res.add_attribute("amount", balance.to_string());
+            if !balance.amount.is_zero() {
+                res = res.add_message(cosmwasm_std::BankMsg::Send {
+                    to_address: to.to_string(),
+                    amount: vec![balance],
+                });
+            }
+        }
+        _ => return Err(ContractError::InvalidRescue {}),
+    }
+    Ok(res)
+}
+
+pub fn execute_delay_payment(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    new_time: Expiration,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if config.schedule_locked {
+        return Err(ContractError::ScheduleLocked {});
+    }
+
+    let mut payment = load_payment(deps.storage, id)?;
+    if payment.paid || payment.stopped || payment.stopping.is_some() {
+        return Err(ContractError::PaymentNotActive {});
+    }
+    if !due_amount(&payment, &env.block)?.is_zero() {
+        return Err(ContractError::AlreadyVested {});
+    }
+    let later = matches!(
+        new_time.partial_cmp(&payment.payment.time),
+        Some(std::cmp::Ordering::Greater)
+    );
+    if !later || payment.payment.milestones.is_some() {
+        return Err(ContractError::InvalidDelay {});
+    }
+
+    if new_time == (Expiration::Never {}) && !config.allow_never {
+        return Err(ContractError::NeverExpires {});
+    }
+    if config.enforce_order {
+        // The delay must not take it past the payment after it
+        let next = payments()
+            .range(
+                deps.storage,
+                Some(Bound::exclusive_int(id)),
+                None,
+                Order::Ascending,
+            )
+            .next()
+            .transpose()?
+            .map(|(_, p)| p.payment.time);
+        if let Some(next) = next {
+            if !in_order(&new_time, &next) {
+                return Err(ContractError::OutOfOrder {});
+            }
+        }
+    }
+
+    let old_time = payment.payment.time;
+    payment.payment.time = new_time;
+    validate_payment(deps.api, &payment.payment)?;
+    payments().save(deps.storage, id.into(), &payment)?;
+    Ok(Response::new()
+        .add_attribute("method", "delay_payment")
+        .add_attribute("id", id.to_string())
+        .add_attribute("old_time", old_time.to_string())
+        .add_attribute("new_time", new_time.to_string()))
+}
+
+pub fn execute_split_payment(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    amount: Uint128,
+    time: Expiration,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if config.schedule_locked {
+        return Err(ContractError::ScheduleLocked {});
+    }
+
+    let mut original = load_payment(deps.storage, id)?;
+    if original.paid || original.stopped || original.stopping.is_some() {
+        return Err(ContractError::PaymentNotActive {});
+    }
+    if !due_amount(&original, &env.block)?.is_zero() {
+        return Err(ContractError::AlreadyVested {});
+    }
+    let p = &original.payment;
+    if amount.is_zero()
+        || amount >= p.amount
+        || p.recurrence.is_some()
+        || p.milestones.is_some()
+        || p.extra_coins.is_some()
+    {
+        return Err(ContractError::InvalidSplitAmount {});
+    }
+    if time == (Expiration::Never {}) && !config.allow_never {
+        return Err(ContractError::NeverExpires {});
+    }
+    if config.enforce_order {
+        // The split gets the newest id, so it must not unlock before the latest entry
+        let last = payments()
+            .range(deps.storage, None, None, Order::Descending)
+            .next()
+            .transpose()?
+            .map(|(_, p)| p.payment.time);
+        if let Some(last) = last {
+            if !in_order(&last, &time) {
+                return Err(ContractError::OutOfOrder {});
+            }
+        }
+    }
+
+    // The amount only moves between payments, so the stats totals are unchanged
+    let split = Payment {
+        amount,
+        time,
+        time_offset: None,
+        ..p.clone()
+    };
+    validate_payment(deps.api, &split)?;
+    original.payment.amount = p.amount - amount;
+    payments().save(deps.storage, id.into(), &original)?;
+
+    let new_id = next_id(deps.storage)?;
+    let state = PaymentState {
+        payment: split,
+        paid: false,
+        id: new_id,
+        installments_paid: 0,
+        stopped: false,
+        paused: original.paused,
+        paid_at: None,
+        stopping: None,
+        fail_count: 0,
+    };
+    payments().save(deps.storage, new_id.into(), &state)?;
+    Ok(Response::new()
+        .add_attribute("method", "split_payment")
+        .add_attribute("id", id.to_string())
+        .add_attribute("new_id", new_id.to_string())
+        .add_attribute("amount", amount))
+}
+
+pub fn execute_top_up_payment(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+    additional: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if config.schedule_locked {
+        return Err(ContractError::ScheduleLocked {});
+    }
+    if additional.is_zero() {
+        return Err(ContractError::InvalidTopUp {});
+    }
+
+    let mut payment = load_payment(deps.storage, id)?;
+    if payment.paid || payment.stopped || payment.stopping.is_some() {
+        return Err(ContractError::PaymentNotActive {});
+    }
+    if !due_amount(&payment, &env.block)?.is_zero() {
+        return Err(ContractError::AlreadyVested {});
+    }
+    // Paid totals are derived from the installment amount, raising it would
+    // overstate what earlier installments paid out
+    if payment.installments_paid > 0 {
+        return Err(ContractError::InstallmentsPaid { id });
+    }
+
+    let before = remaining_payment(&payment)?.amount;
+    payment.payment.amount = payment
+        .payment
+        .amount
+        .checked_add(additional)
+        .map_err(StdError::from)?;
+    let increase = Payment {
+        amount: remaining_payment(&payment)?
+            .amount
+            .checked_sub(before)
+            .map_err(StdError::from)?,
+        extra_coins: None,
+        ..payment.payment.clone()
+    };
+    payments().save(deps.storage, id.into(), &payment)?;
+    update_stats(deps.storage, |stats| {
+        add_payment_amount(
+            &mut stats.scheduled.native,
+            &mut stats.scheduled.cw20,
+            &increase,
+        )?;
+        add_payment_amount(&mut stats.active.native, &mut stats.active.cw20, &increase)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("method", "top_up_payment")
+        .add_attribute("id", id.to_string())
+        .add_attribute("added", increase.amount)
+        .add_attribute("amount", payment.payment.amount))
+}
+
+pub fn execute_approve(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let payment = load_payment(deps.storage, id)?;
+    if info.sender != config.owner && info.sender != payment.payment.recipient {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !payment.payment.require_approval {
+        return Err(ContractError::ApprovalNotRequired {});
+    }
+    if payment.paid || payment.stopped {
+        return Err(ContractError::PaymentNotActive {});
+    }
+
+    APPROVALS.save(deps.storage, (id.into(), &info.sender), &())?;
+    Ok(Response::new()
+        .add_attribute("method", "approve")
+        .add_attribute("id", id.to_string())
+        .add_attribute("approver", info.sender))
+}
+
+// Whether Pay sends the payment's due installments or holds them back
+fn payable(deps: Deps, config: &Config, p: &PaymentState) -> bool {
+    !p.paused && is_approved(deps.storage, config, p) && condition_met(deps, &p.payment)
+}
+
+// Payments without require_approval are always approved
+fn is_approved(storage: &dyn Storage, config: &Config, p: &PaymentState) -> bool {
+    let approved = |addr: &Addr| APPROVALS.has(storage, (p.id.into(), addr));
+    !p.payment.require_approval || approved(&config.owner) && approved(&p.payment.recipient)
+}
+
+// A failing query counts as unmet, so a broken contract only holds back its own payments
+fn condition_met(deps: Deps, p: &Payment) -> bool {
+    let condition = match &p.condition {
+        Some(condition) => condition,
+        None => return true,
+    };
+    let request: QueryRequest<Empty> = QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: condition.contract.to_string(),
+        msg: condition.query.clone(),
+    });
+    let request = match to_vec(&request) {
+        Ok(request) => request,
+        Err(_) => return false,
+    };
+    matches!(
+        deps.querier.raw_query(&request),
+        SystemResult::Ok(ContractResult::Ok(res)) if res == condition.expected
+    )
+}
+
+pub fn execute_set_payout_address(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: Option<Addr>,
+) -> Result<Response, ContractError> {
+    let res = Response::new().add_attribute("method", "set_payout_address");
+    match address {
+        Some(address) => {
+            let address = deps.api.addr_validate(address.as_str())?;
+            PAYOUT_ADDRESSES.save(deps.storage, &info.sender, &address)?;
+            Ok(res.add_attribute("payout_address", address.to_string()))
+        }
+        None => {
+            PAYOUT_ADDRESSES.remove(deps.storage, &info.sender);
+            Ok(res.add_attribute("payout_address", info.sender.to_string()))
+        }
+    }
+}
+
+pub fn execute_revoke(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut payment = load_payment(deps.storage, id)?;
+    if payment.paid || payment.stopped || payment.stopping.is_some() {
+        return Err(ContractError::PaymentNotActive {});
+    }
+    check_stoppable(&payment, &env.block)?;
+    let matured = matured_installments(&payment.payment, &env.block);
+    let total = total_installments(&payment.payment);
+    if matured == total {
+        return Err(ContractError::AlreadyVested {});
+    }
+
+    let refund = released(&payment.payment, matured, total)?;
+    update_stats(deps.storage, |stats| {
+        sub_payment_amount(&mut stats.active.native, &mut stats.active.cw20, &refund)?;
+        add_payment_amount(
+            &mut stats.refunded.native,
+            &mut stats.refunded.cw20,
+            &refund,
+        )
+    })?;
+
+    // Cut the schedule at the unlocked installments so nothing more accrues
+    if matured == 0 {
+        payment.stopped = true;
+        increment_count(deps.storage, &STOPPED_COUNT)?;
+    } else {
+        if let Some(r) = &mut payment.payment.recurrence {
+            r.installments = matured;
+        }
+        if let Some(milestones) = &mut payment.payment.milestones {
+            milestones.truncate(matured as usize);
+        }
+        if payment.installments_paid == matured {
+            payment.paid = true;
+            payment.paid_at = match payment.payment.time {
+                Expiration::AtTime(_) => Some(Expiration::AtTime(env.block.time)),
+                _ => Some(Expiration::AtHeight(env.block.height)),
+            };
+            increment_count(deps.storage, &PAID_COUNT)?;
+        }
+    }
+    payments().save(deps.storage, id.into(), &payment)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "revoke")
+        .add_attribute("id", id.to_string())
+        .add_attribute("vested_installments", matured.to_string())
+        .add_attributes([refund_attribute(id, "revoke")])
+        .add_message(get_payment_message(&refund, &config.owner, &env, &config)?)
+        .set_data(to_binary(&StopPaymentResponse {
+            id,
+            refunded: refund.amount,
+            denom: payment.payment.denom,
+            token_address: payment.payment.token_address,
+        })?))
+}
+
+pub fn execute_clawback(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let payment = load_payment(deps.storage, id)?;
+    if payment.paid || payment.stopped {
+        return Err(ContractError::PaymentNotActive {});
+    }
+    if !payment
+        .payment
+        .expiry
+        .is_some_and(|e| e.is_expired(&env.block))
+    {
+        return Err(ContractError::NotExpired {});
+    }
+
+    record_stop(deps.storage, &payment, true)?;
+    payments().save(
+        deps.storage,
+        id.into(),
+        &PaymentState {
+            stopped: true,
+            ..payment.clone()
+        },
+    )?;
+    increment_count(deps.storage, &STOPPED_COUNT)?;
+
+    // Due but unswept installments are forfeited along with the rest
+    let remaining = remaining_payment(&payment)?;
+    let refunded = remaining.amount;
+    Ok(Response::new()
+        .add_attribute("method", "clawback")
+        .add_attribute("id", id.to_string())
+        .add_attributes([refund_attribute(id, "clawback")])
+        .add_message(get_payment_message(
+            &remaining,
+            &config.owner,
+            &env,
+            &config,
+        )?)
+        .set_data(to_binary(&StopPaymentResponse {
+            id,
+            refunded,
+            denom: payment.payment.denom,
+            token_address: payment.payment.token_address,
+        })?))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_update_config(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    owner: Option<Addr>,
+    enabled: Option<bool>,
+    payout_start: Option<Update<Expiration>>,
+    schedule_locked: Option<bool>,
+    payers: Option<Update<Vec<Addr>>>,
+    ibc_timeout_seconds: Option<Update<u64>>,
+    fee: Option<Update<Fee>>,
+    min_payout: Option<Update<Uint128>>,
+    stop_grace: Option<Update<Duration>>,
+    payout_order: Option<PayoutOrder>,
+    keeper_reward: Option<Update<Coin>>,
+    expected_version: Option<u64>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    // Ownership is the owner's to hand over, every other setting is for admins
+    let settings = [
+        enabled.is_some(),
+        payout_start.is_some(),
+        schedule_locked.is_some(),
+        payers.is_some(),
+        ibc_timeout_seconds.is_some(),
+        fee.is_some(),
+        min_payout.is_some(),
+        stop_grace.is_some(),
+        payout_order.is_some(),
+        keeper_reward.is_some(),
+    ];
+    if owner.is_some() && info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if (owner.is_none() || settings.contains(&true)) && !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if expected_version.is_some_and(|v| v != config.version) {
+        return Err(ContractError::StaleConfig {});
+    }
+
+    if let Some(owner) = owner {
+        config.owner = validate_admin(deps.api, &env, &owner)?;
+    }
+    if let Some(enabled) = enabled {
+        config.enabled = enabled;
+    }
+    if let Some(payout_start) = payout_start {
+        config.payout_start = payout_start.into_option();
+    }
+    if let Some(payers) = payers {
+        config.payers = payers
+            .into_option()
+            .map(|payers| {
+                payers
+                    .iter()
+                    .map(|a| deps.api.addr_validate(a.as_str()))
+                    .collect::<StdResult<Vec<Addr>>>()
+            })
+            .transpose()?;
+    }
+    if let Some(ibc_timeout_seconds) = ibc_timeout_seconds {
+        config.ibc_timeout_seconds = ibc_timeout_seconds.into_option();
+    }
+    if let Some(min_payout) = min_payout {
+        config.min_payout = min_payout.into_option();
+    }
+    if let Some(stop_grace) = stop_grace {
+        config.stop_grace = stop_grace.into_option();
+    }
+    if let Some(payout_order) = payout_order {
+        config.payout_order = payout_order;
+    }
+    if let Some(keeper_reward) = keeper_reward {
+        let keeper_reward = keeper_reward.into_option();
+        if let Some(reward) = &keeper_reward {
+            validate_denom(&reward.denom)?;
+        }
+        config.keeper_reward = keeper_reward;
+    }
+    match fee.map(Update::into_option) {
+        Some(Some(fee)) if fee.bps > 10000 => return Err(ContractError::InvalidFee {}),
+        Some(Some(fee)) => {
+            config.fee_bps = fee.bps;
+            config.fee_collector = Some(deps.api.addr_validate(fee.collector.as_str())?);
+        }
+        Some(None) => {
+            config.fee_bps = 0;
+            config.fee_collector = None;
+        }
+        None => {}
+    }
+    if let Some(locked) = schedule_locked {
+        if config.schedule_locked && !locked {
+            return Err(ContractError::ScheduleLocked {});
+        }
+        config.schedule_locked = locked;
+    }
+    config.version += 1;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "update_config")
+        .add_attribute("version", config.version.to_string()))
+}
+
+pub fn execute_add_admin(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    address: Addr,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let address = validate_admin(deps.api, &env, &address)?;
+    if !config.is_admin(&address) {
+        config.admins.push(address.clone());
+        config.version += 1;
+        CONFIG.save(deps.storage, &config)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("method", "add_admin")
+        .add_attribute("admin", address))
+}
+
+pub fn execute_remove_admin(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: Addr,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if !config.is_admin(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !config.is_admin(&address) {
+        return Err(ContractError::AdminNotFound {});
+    }
+    if config.admins.len() == 1 {
+        return Err(ContractError::CannotRemoveLastAdmin {});
+    }
+
+    config.admins.retain(|a| *a != address);
+    config.version += 1;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "remove_admin")
+        .add_attribute("admin", address))
+}
+
+pub fn execute_fund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    then_pay: bool,
+) -> Result<Response, ContractError> {
+    let mut res = Response::new().add_attribute("method", "fund");
+    if !info.funds.is_empty() {
+        let funded: Vec<String> = info.funds.iter().map(|c| c.to_string()).collect();
+        res = res.add_attribute("funded", funded.join(","));
+    }
+
+    // Funds are already in the contract balance when the sweep runs
+    if then_pay {
+        let pay = execute_pay(deps, env, info, None)?;
+        res = res
+            .add_attributes(pay.attributes)
+            .add_submessages(pay.messages);
+    }
+    Ok(res)
+}
+
+pub fn execute_pay(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.is_payer(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !config.enabled {
+        return Err(ContractError::PayoutsDisabled {});
+    }
+    if let Some(start) = config.payout_start {
+        if !start.is_expired(&env.block) {
+            return Err(ContractError::PayoutsNotStarted {});
+        }
+    }
+
+    let selection = select_payouts(deps.as_ref(), &env, limit, None)?;
+    let to_be_paid = &selection.to_be_paid;
+    // Sorted orders pay out of id order, so they resume after the furthest id
+    // they reached. Ties keep sweep order and so make their way round too
+    let swept = match config.payout_order {
+        PayoutOrder::OldestFirst => to_be_paid.last().map(|(p, _, _)| p.id),
+        _ => to_be_paid.iter().map(|(p, _, _)| p.id).max(),
+    };
+    let last_swept_id = match (limit, swept) {
+        (Some(limit), Some(id)) if to_be_paid.len() >= limit as usize => id,
+        _ => 0,
+    };
+    LAST_SWEPT_ID.save(deps.storage, &last_swept_id)?;
+
+    let mut res = Response::new().add_attribute("method", "pay");
+    if let Some(reward) = keeper_reward(deps.as_ref(), &env, &config, to_be_paid)? {
+        res = res
+            .add_attribute("keeper_reward", reward.to_string())
+            .add_message(cosmwasm_std::BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: vec![reward],
+            });
+    }
+    send_payouts(deps, &env, &config, selection, res)
+}
+
+// The reward is only paid from what the balance holds beyond every active
+// payment's remainder, measured before this sweep's payouts go out
+fn keeper_reward(
+    deps: Deps,
+    env: &Env,
+    config: &Config,
+    to_be_paid: &[(PaymentState, u32, Payment)],
+) -> StdResult<Option<Coin>> {
+    let reward = match &config.keeper_reward {
+        Some(reward) if !to_be_paid.is_empty() && !reward.amount.is_zero() => reward,
+        _ => return Ok(None),
+    };
+    let balance = deps
+        .querier
+        .query_balance(&env.contract.address, &reward.denom)?
+        .amount;
+    let committed = query_outstanding(deps)?
+        .native
+        .into_iter()
+        .find(|c| c.denom == reward.denom)
+        .map(|c| c.amount)
+        .unwrap_or_default();
+    let surplus = balance.saturating_sub(committed);
+    Ok((surplus >= reward.amount).then(|| reward.clone()))
+}
+
+pub fn execute_claim_all(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.enabled {
+        return Err(ContractError::PayoutsDisabled {});
+    }
+    if let Some(start) = config.payout_start {
+        if !start.is_expired(&env.block) {
+            return Err(ContractError::PayoutsNotStarted {});
+        }
+    }
+
+    let ids: Vec<u64> = payments_of(deps.storage, &info.sender)?
+        .iter()
+        .map(|p| p.id)
+        .collect();
+    let selection = select_payouts(deps.as_ref(), &env, None, Some(&ids))?;
+
+    let mut claimed = ClaimableResponse {
+        native: vec![],
+        cw20: vec![],
+    };
+    for (_, _, payout) in selection.to_be_paid.iter() {
+        add_payment_amount(&mut claimed.native, &mut claimed.cw20, payout)?;
+    }
+    let res = Response::new()
+        .add_attribute("method", "claim_all")
+        .set_data(to_binary(&claimed)?);
+    send_payouts(deps, &env, &config, selection, res)
+}
+
+// Adds the payouts to res. Payments are marked paid before sending. Each payout
+// is a submessage so a failing transfer only rolls back its own payment in reply
+fn send_payouts(
+    deps: DepsMut,
+    env: &Env,
+    config: &Config,
+    selection: Selection,
+    mut res: Response,
+) -> Result<Response, ContractError> {
+    // Invalid payouts count as failed ones instead of reverting the sweep
+    for (p, err) in selection.invalid {
+        let id = p.id;
+        let (fail_count, quarantined) = record_failure(deps.storage, p)?;
+        res = res
+            .add_attribute("failed_id", id.to_string())
+            .add_attribute("error", err.to_string())
+            .add_attribute("fail_count", fail_count.to_string());
+        if quarantined {
+            res = res.add_attribute("quarantined_id", id.to_string());
+        }
+    }
+
+    for (p, matured, payout) in selection.to_be_paid.into_iter() {
+        // Selection already skips these, this keeps a stale selection from
+        // paying a stopped payment again
+        let current = load_payment(deps.storage, p.id)?;
+        if current.stopped || current.paid {
+            continue;
+        }
+        record_payout(deps.storage, &payout, true)?;
+        let Transfers {
+            shares,
+            single,
+            mut fee,
+        } = payout_transfers(deps.storage, config, payout)?;
+        // Shares are plain messages, as rolling back after only some of them
+        // went out would pay those again
+        for share in shares {
+            res = res.add_message(get_payment_message(&share, &share.recipient, env, config)?);
+        }
+        if let Some(payout) = single {
+            let msg = get_payment_message(&payout, &payout.recipient, env, config)?;
+            PAYOUT_ROLLBACK.save(deps.storage, p.id.into(), &p)?;
+            // A payout that is rolled back must not have paid its fee
+            if let Some(fee) = fee.take() {
+                PAYOUT_FEE.save(deps.storage, p.id.into(), &fee)?;
+            }
+            // Plain transfers are checked against the balance from before the
+            // sweep, Send recipients may pass the tokens on
+            if let (Some(token), None) = (&payout.token_address, &payout.on_receive) {
+                let balance = cw20_balance(deps.as_ref(), token, &payout.recipient)?;
+                PAYOUT_DELIVERY.save(
+                    deps.storage,
+                    p.id.into(),
+                    &Cw20Delivery {
+                        token: token.clone(),
+                        recipient: payout.recipient.clone(),
+                        min_balance: balance.checked_add(payout.amount).map_err(StdError::from)?,
+                    },
+                )?;
+            }
+            res = res.add_submessage(SubMsg::reply_always(msg, p.id));
+        }
+
+        let id = p.id;
+        let updated = payments().update(deps.storage, id.into(), |p| match p {
+            Some(p) => {
+                let paid = matured == total_installments(&p.payment);
+                let paid_at = match p.payment.time {
+                    _ if !paid => None,
+                    Expiration::AtTime(_) => Some(Expiration::AtTime(env.block.time)),
+                    _ => Some(Expiration::AtHeight(env.block.height)),
+                };
+                Ok(PaymentState {
+                    paid,
+                    installments_paid: matured,
+                    paid_at,
+                    ..p
+                })
+            }
+            None => Err(ContractError::PaymentNotFound { id }),
+        })?;
+        if updated.paid {
+            increment_count(deps.storage, &PAID_COUNT)?;
+        }
+
+        res = res.add_attribute("paid_id", p.id.to_string());
+        // Fees left here are on shared or empty payouts, which can't roll back.
+        // They are plain messages, so a failing fee transfer fails the whole sweep
+        if let Some(fee) = fee {
+            res = res.add_message(get_payment_message(&fee, &fee.recipient, env, config)?);
+        }
+        if let Some(memo) = p.payment.memo {
+            res = res.add_attribute("memo", memo);
+        }
+    }
+
+    Ok(res)
+}
+
+// What a payout is sent as, each transfer to its payout address: the shares of
+// a split or else a single transfer, and the fee
+struct Transfers {
+    shares: Vec<Payment>,
+    single: Option<Payment>,
+    fee: Option<Payment>,
+}
+
+fn payout_transfers(
+    storage: &dyn Storage,
+    config: &Config,
+    payout: Payment,
+) -> StdResult<Transfers> {
+    let (payout, fee) = split_fee(payout, config)?;
+    let mut transfers = Transfers {
+        shares: vec![],
+        single: None,
+        fee,
+    };
+    match &payout.recipients {
+        Some(recipients) => {
+            for (recipient, share) in split_payout(&payout, recipients)? {
+                if has_amount(&share) {
+                    transfers.shares.push(Payment {
+                        recipient: payout_address(storage, config, &share, &recipient)?,
+                        ..share
+                    });
+                }
+            }
+        }
+        // A payout the fee took all of has nothing to send or roll back
+        None if !has_amount(&payout) => {}
+        None => {
+            let recipient = payout_address(storage, config, &payout, &payout.recipient)?;
+            // The callback is for the recipient contract, an address it pays
+            // out to instead just gets a plain transfer
+            let on_receive = match recipient == payout.recipient {
+                true => payout.on_receive.clone(),
+                false => None,
+            };
+            transfers.single = Some(Payment {
+                recipient,
+                on_receive,
+                ..payout
+            });
+        }
+    }
+    Ok(transfers)
+}
+
+// Bank and cw20 transfers reject zero amounts, so an empty payout sends nothing
+fn has_amount(p: &Payment) -> bool {
+    !p.amount.is_zero() || p.extra_coins.iter().flatten().any(|c| !c.amount.is_zero())
+}
+
+// Where a payout to recipient goes. IBC recipients are addresses on another
+// chain and the owner's payments always go to the owner
+fn payout_address(
+    storage: &dyn Storage,
+    config: &Config,
+    payout: &Payment,
+    recipient: &Addr,
+) -> StdResult<Addr> {
+    if payout.ibc_channel.is_some() || *recipient == config.owner {
+        return Ok(recipient.clone());
+    }
+    Ok(PAYOUT_ADDRESSES
+        .may_load(storage, recipient)?
+        .unwrap_or_else(|| recipient.clone()))
+}
+
+// Splits the configured fee off a payout, returning the fee as a payment to
+// the collector unless it rounds down to nothing
+fn split_fee(payout: Payment, config: &Config) -> StdResult<(Payment, Option<Payment>)> {
+    let collector = match &config.fee_collector {
+        Some(collector) if config.fee_bps > 0 => collector.clone(),
+        _ => return Ok((payout, None)),
+    };
+    let fee_of = |amount: Uint128| amount.multiply_ratio(config.fee_bps, 10000u128);
+
+    let fee_amount = fee_of(payout.amount);
+    let mut fee_coins = vec![];
+    let mut extra_coins = payout.extra_coins.clone();
+    for c in extra_coins.iter_mut().flatten() {
+        let fee = fee_of(c.amount);
+        c.amount = c.amount.checked_sub(fee)?;
+        fee_coins.push(Coin {
+            denom: c.denom.clone(),
+            amount: fee,
+        });
+    }
+    if fee_amount.is_zero() && fee_coins.iter().all(|c| c.amount.is_zero()) {
+        return Ok((payout, None));
+    }
+
+    let fee = Payment {
+        recipient: collector,
+        amount: fee_amount,
+        extra_coins: extra_coins.as_ref().map(|_| fee_coins),
+        ibc_channel: None,
+        on_receive: None,
+        ..payout.clone()
+    };
+    let payout = Payment {
+        amount: payout.amount.checked_sub(fee_amount)?,
+        extra_coins,
+        ..payout
+    };
+    Ok((payout, Some(fee)))
+}
+
+// Divides a payout by recipient weight. Rounding on running totals keeps the
+// shares adding up to the payout
+fn split_payout(payout: &Payment, recipients: &[(Addr, u16)]) -> StdResult<Vec<(Addr, Payment)>> {
+    let mut shares = vec![];
+    let mut before: u64 = 0;
+    for (recipient, weight) in recipients {
+        let after = before + *weight as u64;
+        let share = |amount: Uint128| -> StdResult<Uint128> {
+            Ok(amount
+                .multiply_ratio(after, 10000u64)
+                .checked_sub(amount.multiply_ratio(before, 10000u64))?)
+        };
+        let extra_coins = match &payout.extra_coins {
+            Some(extra) => Some(
+                extra
+                    .iter()
+                    .map(|c| {
+                        Ok(Coin {
+                            denom: c.denom.clone(),
+                            amount: share(c.amount)?,
+                        })
+                    })
+                    .collect::<StdResult<Vec<Coin>>>()?,
+            ),
+            None => None,
+        };
+        shares.push((
+            recipient.clone(),
+            Payment {
+                amount: share(payout.amount)?,
+                extra_coins,
+                ..payout.clone()
+            },
+        ));
+        before = after;
+    }
+    Ok(shares)
+}
+
+// Payouts the next Pay sends, along with the installments they bring each
+// payment up to, and the payments whose payout could never be sent
+struct Selection {
+    to_be_paid: Vec<(PaymentState, u32, Payment)>,
+    invalid: Vec<(PaymentState, ContractError)>,
+}
+
+// Checks what the bank module or token contract would reject, as a plain
+// message failing would revert the whole sweep
+fn check_payout(deps: Deps, payout: &Payment, id: u64) -> Result<(), ContractError> {
+    if let Some(address) = &payout.token_address {
+        deps.api
+            .addr_validate(address.as_str())
+            .map_err(|_| ContractError::InvalidTokenAddress { id })?;
+    }
+    let empty_denom = payout.token_address.is_none() && payout.denom.is_empty()
+        || payout
+            .extra_coins
+            .iter()
+            .flatten()
+            .any(|c| c.denom.is_empty());
+    if empty_denom {
+        return Err(ContractError::InvalidDenom {});
+    }
+    Ok(())
+}
+
+// Payments after the last swept id come first so limited sweeps make their way
+// through the whole schedule
+fn select_payouts(
+    deps: Deps,
+    env: &Env,
+    limit: Option<u32>,
+    ids: Option<&[u64]>,
+) -> Result<Selection, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let cursor = LAST_SWEPT_ID.may_load(deps.storage)?.unwrap_or_default();
+    let after = payments().range(
+        deps.storage,
+        Some(Bound::exclusive_int(cursor)),
+        None,
+        Order::Ascending,
+    );
+    let before = payments().range(
+        deps.storage,
+        None,
+        Some(Bound::inclusive_int(cursor)),
+        Order::Ascending,
+    );
+    let mut due = after
+        .chain(before)
+        .filter_map(|r| match r {
+            Ok(r) => Some(r.1),
+            _ => None,
+        })
+        .filter(|p| !p.paid && !p.stopped && !p.paused)
+        .filter(|p| ids.is_none_or(|ids| ids.contains(&p.id)))
+        .map(|p| {
+            let matured = matured_installments(&p.payment, &env.block);
+            (p, matured)
+        })
+        .filter(|(p, matured)| *matured > p.installments_paid)
+        .map(|(p, matured)| {
+            let payout = released(&p.payment, p.installments_paid, matured)?;
+            Ok((p, matured, payout))
+        })
+        .collect::<StdResult<Vec<(PaymentState, u32, Payment)>>>()?;
+    // The sort is stable, so equal payouts stay in sweep order
+    match config.payout_order {
+        PayoutOrder::OldestFirst => {}
+        PayoutOrder::SmallestFirst => due.sort_by_key(|(_, _, payout)| payout.amount),
+        PayoutOrder::LargestFirst => due.sort_by_key(|(_, _, payout)| Reverse(payout.amount)),
+    }
+
+    // Only pay what the contract can cover, in payout_order, leaving the rest for a top up
+    let limit = limit.map(|l| l as usize).unwrap_or(usize::MAX);
+    let mut native_balances: Vec<Coin> = vec![];
+    let mut cw20_balances: Vec<Cw20CoinVerified> = vec![];
+    let mut selection = Selection {
+        to_be_paid: vec![],
+        invalid: vec![],
+    };
+    for (p, matured, payout) in due {
+        if selection.to_be_paid.len() >= limit {
+            break;
+        }
+        if !payable(deps, &config, &p) {
+            continue;
+        }
+        // Checked before querying the balance of a bad token
+        if let Err(err) = check_payout(deps, &payout, p.id) {
+            selection.invalid.push((p, err));
+            continue;
+        }
+        // Dust is left to accumulate unless this is the last installment
+        let dust = config.min_payout.is_some_and(|min| payout.amount < min);
+        if dust && matured < total_installments(&p.payment) {
+            continue;
+        }
+        let mut parts = vec![(
+            payout.token_address.clone(),
+            payout.denom.clone(),
+            payout.amount,
+        )];
+        for c in payout.extra_coins.iter().flatten() {
+            parts.push((None, c.denom.clone(), c.amount));
+        }
+
+        let mut covered = true;
+        for (token_address, denom, amount) in parts.iter() {
+            let balance = contract_balance(
+                deps,
+                env,
+                &mut native_balances,
+                &mut cw20_balances,
+                token_address,
+                denom,
+            )?;
+            covered &= *balance >= *amount;
+        }
+        if !covered {
+            continue;
+        }
+        for (token_address, denom, amount) in parts.iter() {
+            let balance = contract_balance(
+                deps,
+                env,
+                &mut native_balances,
+                &mut cw20_balances,
+                token_address,
+                denom,
+            )?;
+            *balance = balance.checked_sub(*amount).map_err(StdError::from)?;
+        }
+        selection.to_be_paid.push((p, matured, payout));
+    }
+    Ok(selection)
+}
+
+fn cw20_balance(deps: Deps, token: &Addr, address: &Addr) -> StdResult<Uint128> {
+    let res: BalanceResponse = deps.querier.query_wasm_smart(
+        token,
+        &Cw20QueryMsg::Balance {
+            address: address.to_string(),
+        },
+    )?;
+    Ok(res.balance)
+}
+
+// Returns the contract's remaining balance of the token, or the native denom
+// without one, querying it the first time it is needed
+fn contract_balance<'a>(
+    deps: Deps,
+    env: &Env,
+    native: &'a mut Vec<Coin>,
+    cw20: &'a mut Vec<Cw20CoinVerified>,
+    token_address: &Option<Addr>,
+    denom: &str,
+) -> StdResult<&'a mut Uint128> {
+    match token_address {
+        Some(address) => {
+            let i = match cw20.iter().position(|c| c.address == *address) {
+                Some(i) => i,
+                None => {
+                    cw20.push(Cw20CoinVerified {
+                        address: address.clone(),
+                        amount: cw20_balance(deps, address, &env.contract.address)?,
+                    });
+                    cw20.len() - 1
+                }
+            };
+            Ok(&mut cw20[i].amount)
+        }
+        None => {
+            let i = match native.iter().position(|c| c.denom == denom) {
+                Some(i) => i,
+                None => {
+                    let balance = deps.querier.query_balance(&env.contract.address, denom)?;
+                    native.push(balance);
+                    native.len() - 1
+                }
+            };
+            Ok(&mut native[i].amount)
+        }
+    }
+}
+
+// Sends the payment amounts to recipient. The on_receive callback and IBC
+// transfers only apply when paying the payment's own recipient, never on refunds
+pub fn get_payment_message(
+    p: &Payment,
+    recipient: &Addr,
+    env: &Env,
+    config: &Config,
+) -> StdResult<CosmosMsg> {
+    match (&p.token_address, &p.ibc_channel) {
+        (Some(_), _) => get_token_payment(p, recipient),
+        (None, Some(channel)) if *recipient == p.recipient => {
+            let timeout = env.block.time.plus_seconds(config.ibc_timeout_seconds());
+            Ok(get_ibc_payment(p, channel, timeout.into()))
+        }
+        (None, _) => get_native_payment(p, recipient),
+    }
+}
+
+pub fn get_ibc_payment(p: &Payment, channel: &str, timeout: IbcTimeout) -> CosmosMsg {
+    IbcMsg::Transfer {
+        channel_id: channel.to_string(),
+        to_address: p.recipient.to_string(),
+        amount: Coin {
+            denom: p.denom.clone(),
+            amount: p.amount,
+        },
+        timeout,
+    }
+    .into()
+}
+
+fn on_receive_for<'a>(p: &'a Payment, recipient: &Addr) -> Option<&'a Binary> {
+    p.on_receive.as_ref().filter(|_| *recipient == p.recipient)
+}
+
+pub fn get_token_payment(p: &Payment, recipient: &Addr) -> StdResult<CosmosMsg> {
+    let transfer_cw20_msg = match on_receive_for(p, recipient) {
+        Some(msg) => Cw20ExecuteMsg::Send {
+            contract: recipient.to_string(),
+            amount: p.amount,
+            msg: msg.clone(),
+        },
+        None => Cw20ExecuteMsg::Transfer {
+            recipient: recipient.to_string(),
+            amount: p.amount,
+        },
+    };
+
+    let exec_cw20_transfer = WasmMsg::Execute {
+        contract_addr: p.token_address.clone().unwrap().to_string(),
+        msg: to_binary(&transfer_cw20_msg)?,
+        funds: vec![],
+    };
+
+    Ok(exec_cw20_transfer.into())
+}
+
+pub fn get_native_payment(p: &Payment, recipient: &Addr) -> StdResult<CosmosMsg> {
+    let mut amount = vec![Coin {
+        denom: p.denom.clone(),
+        amount: p.amount,
+    }];
+    if let Some(extra) = &p.extra_coins {
+        amount.extend(extra.iter().cloned());
+    }
+    // Rounding can leave a share or fee without some of the coins
+    amount.retain(|c| !c.amount.is_zero());
+
+    // Funds are attached to the callback so transfer and callback are atomic
+    if let Some(msg) = on_receive_for(p, recipient) {
+        let exec_callback = WasmMsg::Execute {
+            contract_addr: recipient.to_string(),
+            msg: msg.clone(),
+            funds: amount,
+        };
+        return Ok(exec_callback.into());
+    }
+
+    let transfer_bank_msg = cosmwasm_std::BankMsg::Send {
+        to_address: recipient.to_string(),
+        amount,
+    };
+
+    Ok(transfer_bank_msg.into())
+}
+
+// Reply ids are payment ids
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    let previous = PAYOUT_ROLLBACK
+        .may_load(deps.storage, msg.id.into())?
+        .ok_or(ContractError::PaymentNotFound { id: msg.id })?;
+    PAYOUT_ROLLBACK.remove(deps.storage, msg.id.into());
+    let delivery = PAYOUT_DELIVERY.may_load(deps.storage, msg.id.into())?;
+    PAYOUT_DELIVERY.remove(deps.storage, msg.id.into());
+    let fee = PAYOUT_FEE.may_load(deps.storage, msg.id.into())?;
+    PAYOUT_FEE.remove(deps.storage, msg.id.into());
+
+    let err = match msg.result {
+        ContractResult::Err(err) => err,
+        ContractResult::Ok(_) => match delivery {
+            Some(d) if cw20_balance(deps.as_ref(), &d.token, &d.recipient)? < d.min_balance => {
+                "cw20 transfer was not received".to_string()
+            }
+            _ => {
+                let current = payments().load(deps.storage, msg.id.into())?;
+                if current.fail_count > 0 {
+                    let state = PaymentState {
+                        fail_count: 0,
+                        ..current
+                    };
+                    payments().save(deps.storage, msg.id.into(), &state)?;
+                }
+                let mut res = Response::new();
+                if let Some(fee) = fee {
+                    let config = CONFIG.load(deps.storage)?;
+                    let fee_msg = get_payment_message(&fee, &fee.recipient, &env, &config)?;
+                    res = res.add_message(fee_msg);
+                }
+                return Ok(res);
+            }
+        },
+    };
+
+    let current = payments().load(deps.storage, msg.id.into())?;
+    if current.paid && !previous.paid {
+        decrement_count(deps.storage, &PAID_COUNT)?;
+    }
+    let payout = released(
+        &previous.payment,
+        previous.installments_paid,
+        current.installments_paid,
+    )?;
+    record_payout(deps.storage, &payout, false)?;
+
+    let (fail_count, quarantined) = record_failure(deps.storage, previous)?;
+    let mut res = Response::new()
+        .add_attribute("method", "payout_failed")
+        .add_attribute("id", msg.id.to_string())
+        .add_attribute("error", err)
+        .add_attribute("fail_count", fail_count.to_string());
+    if quarantined {
+        res = res.add_attribute("quarantined", "true");
+    }
+    Ok(res)
+}
+
+// Stops retrying a payout that keeps failing, returning the new fail count and
+// whether the payment was paused by it
+fn record_failure(storage: &mut dyn Storage, state: PaymentState) -> StdResult<(u32, bool)> {
+    let fail_count = state.fail_count + 1;
+    let quarantined = fail_count >= MAX_PAYOUT_FAILURES && !state.paused;
+    let state = PaymentState {
+        fail_count,
+        paused: state.paused || quarantined,
+        ..state
+    };
+    payments().save(storage, state.id.into(), &state)?;
+    Ok((fail_count, quarantined))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    // The first release stored no config, so its owner has to be named here
+    if CONFIG.may_load(deps.storage)?.is_none() {
+        let owner = msg.owner.ok_or(ContractError::MissingOwner {})?;
+        let owner = validate_admin(deps.api, &env, &owner)?;
+        CONFIG.save(deps.storage, &Config::new(owner.clone(), owner))?;
+    }
+    migrate_payment_count(deps.storage)?;
+    migrate_payment_indexes(deps.storage)?;
+    migrate_status_counts(deps.storage)?;
+    migrate_stats(deps.storage)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::new().add_attribute("method", "migrate"))
+}
+
+// Builds Stats from the stored payments if it was never tracked. Whether a
+// stopped payment was refunded isn't stored, so its remainder counts as refunded
+fn migrate_stats(storage: &mut dyn Storage) -> StdResult<()> {
+    if STATS.may_load(storage)?.is_some() {
+        return Ok(());
+    }
+    STATS.save(storage, &Stats::default())?;
+    let payments = payments()
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, p) in payments {
+        let paid_to = match p.paid {
+            true => total_installments(&p.payment),
+            false => p.installments_paid,
+        };
+        let active = PaymentState {
+            paid: false,
+            stopped: false,
+            installments_paid: 0,
+            ..p.clone()
+        };
+        record_scheduled(storage, &active)?;
+        record_payout(storage, &released(&p.payment, 0, paid_to)?, true)?;
+        if p.stopped {
+            let stopped = PaymentState {
+                installments_paid: paid_to,
+                ..active
+            };
+            record_stop(storage, &stopped, true)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetPayments {} => to_binary(&query_payments(deps)),
+        QueryMsg::GetConfig {} => to_binary(&query_config(deps)?),
+        QueryMsg::Claimable { recipient } => to_binary(&query_claimable(deps, env, recipient)?),
+        QueryMsg::GetPaymentsByStatus { status } => {
+            to_binary(&query_payments_by_status(deps, env, status)?)
+        }
+        QueryMsg::DuePayments { at } => to_binary(&query_due_payments(deps, env, at)?),
+        QueryMsg::DueCount {} => to_binary(&query_due_count(deps, env)?),
+        QueryMsg::NextUnlock {} => to_binary(&query_next_unlock(deps, env)?),
+        QueryMsg::GetPaymentsCount {} => to_binary(&query_payments_count(deps)?),
+        QueryMsg::SimulatePay {} => to_binary(&query_simulate_pay(deps, env)?),
+        QueryMsg::PreviewStop { id } => to_binary(&query_preview_stop(deps, id)?),
+        QueryMsg::RecipientSummary { recipient } => {
+            to_binary(&query_recipient_summary(deps, recipient)?)
+        }
+        QueryMsg::PaymentsInRange { from, to } => {
+            to_binary(&query_payments_in_range(deps, from, to))
+        }
+        QueryMsg::GetState { start_after, limit } => {
+            to_binary(&query_state(deps, env, start_after, limit)?)
+        }
+        QueryMsg::Progress {} => to_binary(&query_progress(deps)?),
+        QueryMsg::Stats {} => to_binary(&query_stats(deps)?),
+        QueryMsg::OutstandingByRecipient { start_after, limit } => {
+            to_binary(&query_outstanding_by_recipient(deps, start_after, limit)?)
+        }
+        QueryMsg::Recipients { start_after, limit } => {
+            to_binary(&query_recipients(deps, start_after, limit)?)
+        }
+        QueryMsg::PaymentsByToken {
+            token_address,
+            start_after,
+            limit,
+        } => to_binary(&query_payments_by_token(
+            deps,
+            token_address,
+            start_after,
+            limit,
+        )?),
+        QueryMsg::GetPaymentsGrouped {} => to_binary(&query_payments_grouped(deps)),
+        QueryMsg::GetBalances {} => to_binary(&query_balances(deps, env)?),
+        QueryMsg::IsAdmin { address } => {
+            let config = CONFIG.load(deps.storage)?;
+            to_binary(&(address == config.owner || config.is_admin(&address)))
+        }
+    }
+}
+
+fn query_payments_in_range(deps: Deps, from: Expiration, to: Expiration) -> PaymentsResponse {
+    let payments = query_payments(deps)
+        .payments
+        .into_iter()
+        .filter(|p| {
+            let time = p.payment.time;
+            matches!(from.partial_cmp(&time), Some(o) if o.is_le())
+                && matches!(time.partial_cmp(&to), Some(o) if o.is_le())
+        })
+        .collect();
+    PaymentsResponse { payments }
+}
+
+fn query_recipient_summary(deps: Deps, recipient: Addr) -> StdResult<RecipientSummaryResponse> {
+    let mut paid = Totals::default();
+    let mut pending = Totals::default();
+    let mut stopped = Totals::default();
+
+    for p in payments_of(deps.storage, &recipient)? {
+        let paid_out = released(&p.payment, 0, p.installments_paid)?;
+        add_payment_amount(&mut paid.native, &mut paid.cw20, &paid_out)?;
+
+        let unpaid = released(
+            &p.payment,
+            p.installments_paid,
+            total_installments(&p.payment),
+        )?;
+        if p.stopped {
+            add_payment_amount(&mut stopped.native, &mut stopped.cw20, &unpaid)?;
+        } else {
+            add_payment_amount(&mut pending.native, &mut pending.cw20, &unpaid)?;
+        }
+    }
+
+    Ok(RecipientSummaryResponse {
+        total_paid: paid,
+        total_pending: pending,
+        total_stopped: stopped,
+    })
+}
+
+// Page size of a paginated query, clamped so a huge limit can't be trusted
+fn calc_limit(requested: Option<u32>) -> usize {
+    requested.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize
+}
+
+fn query_state(
+    deps: Deps,
+    env: Env,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<StateResponse> {
+    let limit = calc_limit(limit);
+    let payments = payments()
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|r| r.map(|(_, p)| p))
+        .collect::<StdResult<Vec<PaymentState>>>()?;
+
+    Ok(StateResponse {
+        config: query_config(deps)?,
+        payments,
+        next_unlock: query_next_unlock(deps, env)?.next_unlock,
+        outstanding: query_outstanding(deps)?,
+    })
+}
+
+// Unpaid remainder of every active payment
+fn query_outstanding(deps: Deps) -> StdResult<Totals> {
+    let mut outstanding = Totals::default();
+    for p in query_payments(deps).payments {
+        if !p.paid && !p.stopped {
+            add_payment_amount(
+                &mut outstanding.native,
+                &mut outstanding.cw20,
+                &remaining_payment(&p)?,
+            )?;
+        }
+    }
+    Ok(outstanding)
+}
+
+fn query_stats(deps: Deps) -> StdResult<StatsResponse> {
+    let stats = STATS.may_load(deps.storage)?.unwrap_or_default();
+    let distinct_denoms = (stats.scheduled.native.len() + stats.scheduled.cw20.len()) as u64;
+    Ok(StatsResponse {
+        total_scheduled: stats.scheduled,
+        total_paid: stats.paid,
+        total_refunded: stats.refunded,
+        total_active: stats.active,
+        distinct_recipients: stats.distinct_recipients,
+        distinct_denoms,
+    })
+}
+
+fn query_outstanding_by_recipient(
+    deps: Deps,
+    start_after: Option<Addr>,
+    limit: Option<u32>,
+) -> StdResult<OutstandingByRecipientResponse> {
+    let limit = calc_limit(limit);
+    let index = &payments().idx.recipient;
+    // Index keys are the length prefixed recipient followed by the 8 byte id,
+    // so bounding past every id of a recipient skips to the next one
+    let after = |recipient: &Addr| {
+        let mut key = index.index_key((recipient.as_bytes().to_vec(), vec![]));
+        key.extend([0xff; 8]);
+        Bound::Exclusive(key)
+    };
+    let next_recipient = |start: Option<Bound>| -> StdResult<Option<Addr>> {
+        match index
+            .keys(deps.storage, start, None, Order::Ascending)
+            .next()
+        {
+            Some(key) => {
+                let len = u16::from_be_bytes([key[0], key[1]]) as usize;
+                let recipient = String::from_utf8(key[2..2 + len].to_vec())?;
+                Ok(Some(Addr::unchecked(recipient)))
+            }
+            None => Ok(None),
+        }
+    };
+
+    // At most limit recipients are scanned, whether or not they are owed
+    // anything, so a page can't walk every recipient ever seen
+    let mut recipients = vec![];
+    let mut last_scanned = start_after;
+    for _ in 0..limit {
+        let recipient = match next_recipient(last_scanned.as_ref().map(after))? {
+            Some(recipient) => recipient,
+            None => {
+                last_scanned = None;
+                break;
+            }
+        };
+        last_scanned = Some(recipient.clone());
+
+        let mut outstanding = Totals::default();
+        for p in payments_of(deps.storage, &recipient)? {
+            if p.paid || p.stopped {
+                continue;
+            }
+            add_payment_amount(
+                &mut outstanding.native,
+                &mut outstanding.cw20,
+                &remaining_payment(&p)?,
+            )?;
+        }
+        if outstanding != Totals::default() {
+            recipients.push(RecipientOutstanding {
+                recipient,
+                outstanding,
+            });
+        }
+    }
+    let next_start_after = match last_scanned {
+        Some(last) if next_recipient(Some(after(&last)))?.is_some() => Some(last),
+        _ => None,
+    };
+    Ok(OutstandingByRecipientResponse {
+        recipients,
+        next_start_after,
+    })
+}
+
+fn query_recipients(
+    deps: Deps,
+    start_after: Option<Addr>,
+    limit: Option<u32>,
+) -> StdResult<RecipientsResponse> {
+    let limit = calc_limit(limit);
+    let start = start_after.map(|addr| Bound::exclusive(addr.as_bytes()));
+    let recipients = SEEN_RECIPIENTS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|key| {
+            String::from_utf8(key)
+                .map(Addr::unchecked)
+                .map_err(StdError::from)
+        })
+        .collect::<StdResult<Vec<Addr>>>()?;
+    Ok(RecipientsResponse { recipients })
+}
+
+// Filters the id range, as there is no index on token
+fn query_payments_by_token(
+    deps: Deps,
+    token_address: Addr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<PaymentsResponse> {
+    let limit = calc_limit(limit);
+    let payments = payments()
+        .range(
+            deps.storage,
+            start_after.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .filter(|r| match r {
+            Ok((_, p)) => p.payment.token_address.as_ref() == Some(&token_address),
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|r| r.map(|(_, p)| p))
+        .collect::<StdResult<Vec<PaymentState>>>()?;
+    Ok(PaymentsResponse { payments })
+}
+
+fn query_progress(deps: Deps) -> StdResult<ProgressResponse> {
+    let mut total = Uint128::zero();
+    let mut released_total = Uint128::zero();
+    for p in query_payments(deps).payments {
+        let paid = released(&p.payment, 0, p.installments_paid)?.amount;
+        released_total = released_total.checked_add(paid)?;
+        total = match p.stopped {
+            true => total.checked_add(paid)?,
+            false => total.checked_add(unlocked_amount(
+                &p.payment,
+                p.payment.amount,
+                total_installments(&p.payment),
+            )?)?,
+        };
+    }
+
+    let percent_bps = match total.is_zero() {
+        true => 0,
+        false => released_total.multiply_ratio(10000u128, total).u128() as u16,
+    };
+    Ok(ProgressResponse {
+        total,
+        released: released_total,
+        percent_bps,
+    })
+}
+
+fn query_payments_grouped(deps: Deps) -> GroupedPaymentsResponse {
+    let mut res = GroupedPaymentsResponse {
+        active: vec![],
+        paid: vec![],
+        stopped: vec![],
+    };
+    for p in query_payments(deps).payments {
+        match (p.paid, p.stopped) {
+            (true, _) => res.paid.push(p),
+            (_, true) => res.stopped.push(p),
+            _ => res.active.push(p),
+        }
+    }
+    res
+}
+
+fn query_balances(deps: Deps, env: Env) -> StdResult<BalancesResponse> {
+    let mut balances = Totals::default();
+    for p in query_payments(deps).payments {
+        match &p.payment.token_address {
+            Some(token) => {
+                if !balances.cw20.iter().any(|c| c.address == *token) {
+                    balances.cw20.push(Cw20CoinVerified {
+                        address: token.clone(),
+                        amount: cw20_balance(deps, token, &env.contract.address)?,
+                    });
+                }
+            }
+            None => {
+                let denoms = std::iter::once(&p.payment.denom)
+                    .chain(p.payment.extra_coins.iter().flatten().map(|c| &c.denom));
+                for denom in denoms {
+                    if !balances.native.iter().any(|c| c.denom == *denom) {
+                        balances
+                            .native
+                            .push(deps.querier.query_balance(&env.contract.address, denom)?);
+                    }
+                }
+            }
+        }
+    }
+    Ok(BalancesResponse {
+        balances,
+        outstanding: query_outstanding(deps)?,
+    })
+}
+
+fn query_simulate_pay(deps: Deps, env: Env) -> StdResult<SimulatePayResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    if !config.enabled {
+        return Ok(SimulatePayResponse { payouts: vec![] });
+    }
+    if let Some(start) = config.payout_start {
+        if !start.is_expired(&env.block) {
+            return Ok(SimulatePayResponse { payouts: vec![] });
+        }
+    }
+
+    // Built the way send_payouts sends them, so fees, splits and payout
+    // addresses match what Pay does
+    let mut payouts = vec![];
+    let selection =
+        select_payouts(deps, &env, None, None).map_err(|e| StdError::generic_err(e.to_string()))?;
+    for (p, _, payout) in selection.to_be_paid {
+        let transfers = payout_transfers(deps.storage, &config, payout)?;
+        for t in transfers
+            .shares
+            .into_iter()
+            .chain(transfers.single)
+            .chain(transfers.fee)
+        {
+            payouts.push(Payout {
+                id: p.id,
+                recipient: t.recipient,
+                amount: t.amount,
+                denom: t.denom,
+                token_address: t.token_address,
+                extra_coins: t.extra_coins,
+            });
+        }
+    }
+    Ok(SimulatePayResponse { payouts })
+}
+
+fn query_payments_count(deps: Deps) -> StdResult<PaymentsCountResponse> {
+    Ok(PaymentsCountResponse {
+        total: PAYMENT_COUNT.may_load(deps.storage)?.unwrap_or_default(),
+        paid: PAID_COUNT.may_load(deps.storage)?.unwrap_or_default(),
+        stopped: STOPPED_COUNT.may_load(deps.storage)?.unwrap_or_default(),
+    })
+}
+
+fn query_next_unlock(deps: Deps, env: Env) -> StdResult<NextUnlockResponse> {
+    let mut next_unlock: Option<Expiration> = None;
+    for p in query_payments(deps).payments.into_iter() {
+        if let Some(unlock) = next_installment_time(&p, &env.block)? {
+            // Height and time based unlocks are not comparable, the first one found wins
+            if next_unlock.is_none_or(|next| unlock < next) {
+                next_unlock = Some(unlock);
+            }
+        }
+    }
+    Ok(NextUnlockResponse { next_unlock })
+}
+
+// Unlock time of the next installment that has not matured yet
+fn next_installment_time(p: &PaymentState, block: &BlockInfo) -> StdResult<Option<Expiration>> {
+    if p.paid || p.stopped || p.payment.time == (Expiration::Never {}) {
+        return Ok(None);
+    }
+    let matured = matured_installments(&p.payment, block);
+    if matured >= total_installments(&p.payment) {
+        return Ok(None);
+    }
+    match (&p.payment.recurrence, &p.payment.milestones) {
+        (Some(r), _) if matured > 0 => Ok(Some((p.payment.time + r.interval * matured as u64)?)),
+        (_, Some(milestones)) => Ok(Some(milestones[matured as usize].time)),
+        _ => Ok(Some(p.payment.time)),
+    }
+}
+
+fn query_due_payments(deps: Deps, env: Env, at: Option<Expiration>) -> StdResult<PaymentsResponse> {
+    let mut block = env.block;
+    match at {
+        Some(Expiration::AtHeight(height)) => block.height = height,
+        Some(Expiration::AtTime(time)) => block.time = time,
+        Some(Expiration::Never {}) => return Ok(PaymentsResponse { payments: vec![] }),
+        None => {}
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    if let Some(start) = config.payout_start {
+        if !start.is_expired(&block) {
+            return Ok(PaymentsResponse { payments: vec![] });
+        }
+    }
+
+    let mut payments = vec![];
+    for p in query_payments(deps).payments.into_iter() {
+        if payable(deps, &config, &p) && !due_amount(&p, &block)?.is_zero() {
+            payments.push(p);
+        }
+    }
+    Ok(PaymentsResponse { payments })
+}
+
+fn query_preview_stop(deps: Deps, id: u64) -> StdResult<StopPaymentResponse> {
+    let payment = payments().load(deps.storage, id.into())?;
+    Ok(StopPaymentResponse {
+        id,
+        refunded: remaining_payment(&payment)?.amount,
+        denom: payment.payment.denom,
+        token_address: payment.payment.token_address,
+    })
+}
+
+fn query_due_count(deps: Deps, env: Env) -> StdResult<DueCountResponse> {
+    let count = query_due_payments(deps, env, None)?.payments.len();
+    Ok(DueCountResponse {
+        count: count as u64,
+    })
+}
+
+fn query_payments_by_status(
+    deps: Deps,
+    env: Env,
+    status: PaymentStatus,
+) -> StdResult<PaymentsResponse> {
+    if status == PaymentStatus::Paid {
+        let payments = payments()
+            .idx
+            .paid
+            .prefix(U8Key::new(1))
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|r| r.map(|(_, p)| p))
+            .collect::<StdResult<Vec<PaymentState>>>()?;
+        return Ok(PaymentsResponse { payments });
+    }
+
+    // Due installments Pay holds back leave the payment pending
+    let config = CONFIG.load(deps.storage)?;
+    let mut payments = vec![];
+    for p in query_payments(deps).payments.into_iter() {
+        let claimable = payable(deps, &config, &p) && !due_amount(&p, &env.block)?.is_zero();
+        let matches = match status {
+            PaymentStatus::Paid => p.paid,
+            PaymentStatus::Stopped => p.stopped,
+            PaymentStatus::Pending => !p.paid && !p.stopped && !claimable,
+            PaymentStatus::Claimable => claimable,
+        };
+        if matches {
+            payments.push(p);
+        }
+    }
+    Ok(PaymentsResponse { payments })
+}
+
+// Payments to recipient by id, through the recipient index
+fn payments_of(storage: &dyn Storage, recipient: &Addr) -> StdResult<Vec<PaymentState>> {
+    payments()
+        .idx
+        .recipient
+        .prefix(recipient.as_bytes().to_vec())
+        .range(storage, None, None, Order::Ascending)
+        .map(|r| r.map(|(_, p)| p))
+        .collect()
+}
+
+fn query_claimable(deps: Deps, env: Env, recipient: Addr) -> StdResult<ClaimableResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let mut res = ClaimableResponse {
+        native: vec![],
+        cw20: vec![],
+    };
+
+    for p in payments_of(deps.storage, &recipient)? {
+        if !payable(deps, &config, &p) {
+            continue;
+        }
+        let due = due_payment(&p, &env.block)?;
+        add_payment_amount(&mut res.native, &mut res.cw20, &due)?;
+    }
+
+    Ok(res)
+}
+
+// Takes the payment amounts off the matching totals, dropping totals that reach zero
+fn sub_payment_amount(
+    native: &mut Vec<Coin>,
+    cw20: &mut Vec<Cw20CoinVerified>,
+    p: &Payment,
+) -> StdResult<()> {
+    if let Some(address) = &p.token_address {
+        if let Some(c) = cw20.iter_mut().find(|c| c.address == *address) {
+            c.amount = c.amount.checked_sub(p.amount)?;
+        }
+        cw20.retain(|c| !c.amount.is_zero());
+        return Ok(());
+    }
+
+    let primary = Coin {
+        denom: p.denom.clone(),
+        amount: p.amount,
+    };
+    for coin in std::iter::once(&primary).chain(p.extra_coins.iter().flatten()) {
+        if let Some(c) = native.iter_mut().find(|c| c.denom == coin.denom) {
+            c.amount = c.amount.checked_sub(coin.amount)?;
+        }
+    }
+    native.retain(|c| !c.amount.is_zero());
+    Ok(())
+}
+
+// Adds the payment amounts to the matching denom or token totals, skipping zeros
+fn add_payment_amount(
+    native: &mut Vec<Coin>,
+    cw20: &mut Vec<Cw20CoinVerified>,
+    p: &Payment,
+) -> StdResult<()> {
+    if let Some(address) = &p.token_address {
+        if !p.amount.is_zero() {
+            match cw20.iter_mut().find(|c| c.address == *address) {
+                Some(c) => c.amount = c.amount.checked_add(p.amount)?,
+                None => cw20.push(Cw20CoinVerified {
+                    address: address.clone(),
+                    amount: p.amount,
+                }),
+            }
+        }
+        return Ok(());
+    }
+
+    let primary = Coin {
+        denom: p.denom.clone(),
+        amount: p.amount,
+    };
+    for coin in std::iter::once(&primary).chain(p.extra_coins.iter().flatten()) {
+        if coin.amount.is_zero() {
+            continue;
+        }
+        match native.iter_mut().find(|c| c.denom == coin.denom) {
+            Some(c) => c.amount = c.amount.checked_add(coin.amount)?,
+            None => native.push(coin.clone()),
+        }
+    }
+    Ok(())
+}
+
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let ibc_timeout_seconds = config.ibc_timeout_seconds();
+    Ok(ConfigResponse {
+        owner: config.owner,
+        admins: config.admins,
+        payout_start: config.payout_start,
+        schedule_locked: config.schedule_locked,
+        payers: config.payers,
+        enforce_order: config.enforce_order,
+        ibc_timeout_seconds,
+        fee_bps: config.fee_bps,
+        fee_collector: config.fee_collector,
+        min_payout: config.min_payout,
+        default_denom: config.default_denom,
+        stop_grace: config.stop_grace,
+        allowed_denoms: config.allowed_denoms,
+        allow_never: config.allow_never,
+        terminated: config.terminated,
+        payout_order: config.payout_order,
+        version: config.version,
+        keeper_reward: config.keeper_reward,
+        enabled: config.enabled,
+    })
+}
+
+fn query_payments(deps: Deps) -> PaymentsResponse {
+    PaymentsResponse {
+        payments: payments()
+            .range(deps.storage, None, None, Order::Ascending)
+            .filter_map(|p| match p {
+                Ok(p) => Some(p.1),
+                Err(_) => None,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::{Condition, Milestone, Recurrence};
+    use crate::state::LEGACY_PAYMENT_COUNT;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info, MockApi, MockStorage};
+    use cosmwasm_std::{attr, coin, coins, from_binary, Empty};
+    use cw20::{Cw20Coin, Cw20Contract, Cw20ReceiveMsg};
+    use cw_multi_test::{next_block, App, BankKeeper, Contract, ContractWrapper, Executor};
+    use cw_storage_plus::Item;
+    use serde::{Deserialize, Serialize};
+
+    const OWNER: &str = "owner0001";
+    const FUNDER: &str = "funder";
+    const PAYEE2: &str = "payee0002";
+    const PAYEE3: &str = "payee0003";
+
+    const INITIAL_BALANCE: u128 = 2000000;
+
+    pub fn contract_vest() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            crate::contract::execute,
+            crate::contract::instantiate,
+            crate::contract::query,
+        )
+        .with_reply(crate::contract::reply);
+        Box::new(contract)
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ReceiverMsg {
+        Stake {},
+        Receive(Cw20ReceiveMsg),
+    }
+
+    // Counts the callbacks it receives
+    const RECEIVED: Item<u32> = Item::new("received");
+
+    pub fn contract_receiver() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            |deps, _, _, _: ReceiverMsg| -> StdResult<Response> {
+                let received = RECEIVED.may_load(deps.storage)?.unwrap_or_default();
+                RECEIVED.save(deps.storage, &(received + 1))?;
+                Ok(Response::new())
+            },
+            |_, _, _, _: Empty| -> StdResult<Response> { Ok(Response::new()) },
+            |deps, _, _: Empty| -> StdResult<Binary> {
+                to_binary(&RECEIVED.may_load(deps.storage)?.unwrap_or_default())
+            },
+        );
+        Box::new(contract)
+    }
+
+    // Answers every query with the bool it was last told
+    const ORACLE_VALUE: Item<bool> = Item::new("oracle_value");
+
+    pub fn contract_oracle() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            |deps, _, _, value: bool| -> StdResult<Response> {
+                ORACLE_VALUE.save(deps.storage, &value)?;
+                Ok(Response::new())
+            },
+            |_, _, _, _: Empty| -> StdResult<Response> { Ok(Response::new()) },
+            |deps, _, _: Empty| -> StdResult<Binary> {
+                to_binary(&ORACLE_VALUE.may_load(deps.storage)?.unwrap_or_default())
+            },
+        );
+        Box::new(contract)
+    }
+
+    // cw20 that reports a large balance but fails every transfer
+    pub fn contract_failing_cw20() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            |_, _, _, _: Cw20ExecuteMsg| -> StdResult<Response> {
+                Err(StdError::generic_err("transfer failed"))
+            },
+            |_, _, _, _: Empty| -> StdResult<Response> { Ok(Response::new()) },
+            |_, _, _: Cw20QueryMsg| -> StdResult<Binary> {
+                to_binary(&BalanceResponse {
+                    balance: Uint128::new(INITIAL_BALANCE),
+                })
+            },
+        );
+        Box::new(contract)
+    }
+
+    // cw20 that accepts every transfer without moving any tokens
+    pub fn contract_noop_cw20() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            |_, _, _, _: Cw20ExecuteMsg| -> StdResult<Response> { Ok(Response::new()) },
+            |_, _, _, _: Empty| -> StdResult<Response> { Ok(Response::new()) },
+            |_, _, _: Cw20QueryMsg| -> StdResult<Binary> {
+                to_binary(&BalanceResponse {
+                    balance: Uint128::new(INITIAL_BALANCE),
+                })
+            },
+        );
+        Box::new(contract)
+    }
+
+    pub fn contract_cw20() -> Box<dyn Contract<Empty>> {
+        let contract = ContractWrapper::new(
+            cw20_base::contract::execute,
+            cw20_base::contract::instantiate,
+            cw20_base::contract::query,
+        );
+        Box::new(contract)
+    }
+
+    fn mock_app() -> App {
+        let env = mock_env();
+        let api = MockApi::default();
+        let bank = BankKeeper::new();
+
+        App::new(api, env.block, bank, MockStorage::new())
+    }
+
+    // uploads code and returns address of cw20 contract
+    fn instantiate_cw20(app: &mut App) -> Addr {
+        let cw20_id = app.store_code(contract_cw20());
+        let msg = cw20_base::msg::InstantiateMsg {
+            name: String::from("Test"),
+            symbol: String::from("TEST"),
+            decimals: 6,
+            initial_balances: vec![
+                Cw20Coin {
+                    address: OWNER.to_string(),
+                    amount: Uint128::new(INITIAL_BALANCE),
+                },
+                Cw20Coin {
+                    address: FUNDER.to_string(),
+                    amount: Uint128::new(INITIAL_BALANCE),
+                },
+                Cw20Coin {
+                    address: PAYEE2.to_string(),
+                    amount: Uint128::new(INITIAL_BALANCE),
+                },
+                Cw20Coin {
+                    address: PAYEE3.to_string(),
+                    amount: Uint128::new(INITIAL_BALANCE * 2),
+                },
+            ],
+            mint: None,
+            marketing: None,
+        };
+        app.instantiate_contract(cw20_id, Addr::unchecked(OWNER), &msg, &[], "cw20", None)
+            .unwrap()
+    }
+
+    // Instantiated by and administered by OWNER, with every setting off
+    fn instantiate_msg(schedule: Vec<Payment>) -> InstantiateMsg {
+        InstantiateMsg {
+            schedule,
+            admin: Addr::unchecked(OWNER),
+            enforce_order: false,
+            expected_cw20_total: None,
+            default_denom: None,
+            allowed_denoms: None,
+            allow_never: None,
+        }
+    }
+
+    // One-off ujuno payment with every optional feature off. Tests set the rest
+    // with struct update syntax, so new fields only need adding here
+    fn native_payment(recipient: impl Into<String>, amount: u128, time: Expiration) -> Payment {
+        Payment {
+            recipient: Addr::unchecked(recipient),
+            amount: Uint128::new(amount),
+            denom: "ujuno".to_string(),
+            token_address: None,
+            time,
+            time_offset: None,
+            recurrence: None,
+            milestones: None,
+            extra_coins: None,
+            ibc_channel: None,
+            expiry: None,
+            active_from: None,
+            memo: None,
+            on_receive: None,
+            grant_id: None,
+            recipients: None,
+            condition: None,
+            require_approval: false,
+        }
+    }
+
+    fn cw20_payment(
+        recipient: impl Into<String>,
+        amount: u128,
+        token: &Addr,
+        time: Expiration,
+    ) -> Payment {
+        Payment {
+            denom: String::new(),
+            token_address: Some(token.clone()),
+            ..native_payment(recipient, amount, time)
+        }
+    }
+
+    fn instantiate_vest(app: &mut App, payments: Vec<Payment>) -> Addr {
+        let flex_id = app.store_code(contract_vest());
+        let msg = instantiate_msg(payments);
+        app.instantiate_contract(flex_id, Addr::unchecked(OWNER), &msg, &[], "flex", None)
+            .unwrap()
+    }
+
+    fn get_accounts() -> (Addr, Addr, Addr, Addr) {
+        let owner: Addr = Addr::unchecked(OWNER);
+        let funder: Addr = Addr::unchecked(FUNDER);
+        let voter2: Addr = Addr::unchecked(PAYEE2);
+        let voter3: Addr = Addr::unchecked(PAYEE3);
+
+        (owner, funder, voter2, voter3)
+    }
+
+    fn fund_vest_contract(app: &mut App, vest: Addr, cw20: Addr, funder: Addr, amount: Uint128) {
+        app.execute_contract(
+            funder,
+            cw20,
+            &Cw20ExecuteMsg::Transfer {
+                recipient: vest.to_string(),
+                amount,
+            },
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn proper_initialization() {
+        let mut deps = mock_dependencies(&[]);
+
+        let msg = instantiate_msg(vec![]);
+        let info = mock_info("creator", &coins(1000, "earth"));
+
+        // we can just call .unwrap() to assert this was a success
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // it worked, let's query the state
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetPayments {}).unwrap();
+        let value: PaymentsResponse = from_binary(&res).unwrap();
+        assert_eq!(0, value.payments.len());
+    }
+
+    #[test]
+    fn get_payments() {
+        let mut deps = mock_dependencies(&[]);
+
+        let payment = native_payment(String::from("test"), 1, Expiration::AtHeight(1));
+        let payment2 = payment.clone();
+        let msg = instantiate_msg(vec![payment.clone(), payment2]);
+        let info = mock_info("creator", &coins(1000, "earth"));
+
+        // we can just call .unwrap() to assert this was a success
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // it worked, let's query the state
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetPayments {}).unwrap();
+        let value: PaymentsResponse = from_binary(&res).unwrap();
+        assert_eq!(2, value.payments.len());
+    }
+
+    #[test]
+    fn proper_initialization_integration() {
+        let mut app = mock_app();
+
+        let (owner, _funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let _cw20 = Cw20Contract(cw20_addr.clone());
+
+        let payments = vec![native_payment(owner, 1, Expiration::AtHeight(1))];
+
+        let _vest_addr = instantiate_vest(&mut app, payments);
+    }
+
+    #[test]
+    fn single_cw20_payment() {
+        let mut app = mock_app();
+
+        let (owner, funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+
+        let payments = vec![cw20_payment(
+            owner.clone(),
+            1,
+            &cw20_addr,
+            Expiration::AtHeight(1),
+        )];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr.clone(),
+            funder.clone(),
+            Uint128::new(1),
+        );
+
+        let owner_balance = |app: &App<Empty>| cw20.balance(app, owner.clone()).unwrap().u128();
+        let initial_balance = owner_balance(&app);
+        let vest_balance = cw20.balance(&app, vest_addr.clone()).unwrap().u128();
+        assert_eq!(vest_balance, 1);
+
+        // Payout vested tokens
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+
+        // Assert payment is not executed twice
+        app.execute_contract(_payee3, vest_addr, &ExecuteMsg::Pay { limit: None }, &[])
+            .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+    }
+
+    #[test]
+    fn multiple_cw20_payment() {
+        let mut app = mock_app();
+
+        let (owner, funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+
+        let current_height = app.block_info().height;
+
+        let payments = vec![
+            cw20_payment(
+                owner.clone(),
+                1,
+                &cw20_addr,
+                Expiration::AtHeight(current_height + 1),
+            ),
+            cw20_payment(
+                owner.clone(),
+                2,
+                &cw20_addr,
+                Expiration::AtHeight(current_height + 2),
+            ),
+            cw20_payment(
+                owner.clone(),
+                2,
+                &cw20_addr,
+                Expiration::AtHeight(current_height + 2),
+            ),
+            cw20_payment(
+                owner.clone(),
+                5,
+                &cw20_addr,
+                Expiration::AtHeight(current_height + 3),
+            ),
+        ];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr.clone(),
+            funder.clone(),
+            Uint128::new(10),
+        );
+
+        let owner_balance = |app: &App<Empty>| cw20.balance(app, owner.clone()).unwrap().u128();
+        let initial_balance = owner_balance(&app);
+        let vest_balance = cw20.balance(&app, vest_addr.clone()).unwrap().u128();
+        assert_eq!(vest_balance, 10);
+
+        // Payout vested tokens
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(owner_balance(&app), initial_balance);
+
+        // Update block and pay first payment
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+
+        // Update block and make 2nd and 3rd payments
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 5);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 5);
+
+        // Update block and make 4th payments
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 10);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 10);
+
+        // Assert contract has spent all funds
+        let vest_balance = cw20.balance(&app, vest_addr.clone()).unwrap().u128();
+        assert_eq!(vest_balance, 0);
+    }
+
+    #[test]
+    fn single_native_payment() {
+        let mut app = mock_app();
+
+        let (owner, _funder, _payee2, _payee3) = get_accounts();
+
+        let denom = String::from("ujuno");
+        let payments = vec![Payment {
+            denom: denom.clone(),
+            ..native_payment(owner.clone(), 1, Expiration::AtHeight(1))
+        }];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+
+        // Fund vest contract
+        app.init_bank_balance(&vest_addr, vec![coin(1, denom.clone())])
+            .unwrap();
+
+        let owner_balance = |app: &App<Empty>| {
+            app.wrap()
+                .query_balance(owner.clone(), denom.clone())
+                .unwrap()
+                .amount
+                .u128()
+        };
+        let initial_balance = owner_balance(&app);
+
+        // Payout vested tokens
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+
+        // Assert payment is not executed twice
+        app.execute_contract(_payee3, vest_addr, &ExecuteMsg::Pay { limit: None }, &[])
+            .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+    }
+
+    #[test]
+    fn multiple_native_payment() {
+        let mut app = mock_app();
+
+        let (owner, _funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let _cw20 = Cw20Contract(cw20_addr.clone());
+
+        let current_height = app.block_info().height;
+
+        let denom = String::from("ujuno");
+        let payments = vec![
+            Payment {
+                denom: denom.clone(),
+                ..native_payment(owner.clone(), 1, Expiration::AtHeight(current_height + 1))
+            },
+            Payment {
+                denom: denom.clone(),
+                ..native_payment(owner.clone(), 2, Expiration::AtHeight(current_height + 2))
+            },
+            Payment {
+                denom: denom.clone(),
+                ..native_payment(owner.clone(), 2, Expiration::AtHeight(current_height + 2))
+            },
+            Payment {
+                denom: denom.clone(),
+                ..native_payment(owner.clone(), 5, Expiration::AtHeight(current_height + 3))
+            },
+        ];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+
+        // Fund vest contract
+        app.init_bank_balance(&vest_addr, vec![coin(10, denom.clone())])
+            .unwrap();
+
+        let owner_balance = |app: &App<Empty>| {
+            app.wrap()
+                .query_balance(owner.clone(), denom.clone())
+                .unwrap()
+                .amount
+                .u128()
+        };
+        let initial_balance = owner_balance(&app);
+
+        // Payout vested tokens
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(owner_balance(&app), initial_balance);
+
+        // Update block and pay first payment
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 1);
+
+        // Update block and make 2nd and 3rd payments
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 5);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 5);
+
+        // Update block and make 4th payments
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 10);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 10);
+    }
+
+    #[test]
+    fn native_and_token_payments() {
+        let mut app = mock_app();
+
+        let (owner, funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+
+        let current_height = app.block_info().height;
+
+        let denom = String::from("ujuno");
+        let payments = vec![
+            Payment {
+                denom: denom.clone(),
+                ..native_payment(owner.clone(), 1, Expiration::AtHeight(current_height + 1))
+            },
+            cw20_payment(
+                owner.clone(),
+                2,
+                &cw20_addr,
+                Expiration::AtHeight(current_height + 2),
+            ),
+            Payment {
+                denom: denom.clone(),
+                ..native_payment(owner.clone(), 2, Expiration::AtHeight(current_height + 2))
+            },
+            cw20_payment(
+                owner.clone(),
+                5,
+                &cw20_addr,
+                Expiration::AtHeight(current_height + 3),
+            ),
+        ];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+
+        // Fund vest contract
+        app.init_bank_balance(&vest_addr, vec![coin(3, denom.clone())])
+            .unwrap();
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr.clone(),
+            funder.clone(),
+            Uint128::new(7),
+        );
+
+        let owner_balance_cw20 =
+            |app: &App<Empty>| cw20.balance(app, owner.clone()).unwrap().u128();
+        let owner_balance_juno = |app: &App<Empty>| {
+            app.wrap()
+                .query_balance(owner.clone(), denom.clone())
+                .unwrap()
+                .amount
+                .u128()
+        };
+        let initial_balance_cw20 = owner_balance_cw20(&app);
+        let initial_balance_juno = owner_balance_juno(&app);
+
+        // Payout vested tokens
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20);
+        assert_eq!(owner_balance_juno(&app), initial_balance_juno);
+
+        // Update block and pay first payment
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20);
+        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 1);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20);
+        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 1);
+
+        // Update block and make 2nd and 3rd payments
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 2);
+        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 2);
+        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+
+        // Update block and make 4th payments
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 7);
+        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+
+        // Check second call does not make more payments
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 7);
+        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+    }
+
+    #[test]
+    fn multiple_admins() {
+        let mut deps = mock_dependencies(&[]);
+
+        let msg = instantiate_msg(vec![]);
+        let info = mock_info(OWNER, &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // Non admins cannot add admins
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PAYEE2, &[]),
+            ExecuteMsg::AddAdmin {
+                address: Addr::unchecked(PAYEE2),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // Owner adds a second admin
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::AddAdmin {
+                address: Addr::unchecked(PAYEE2),
+            },
+        )
+        .unwrap();
+
+        // New admin has the same rights as the owner
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PAYEE2, &[]),
+            ExecuteMsg::AddAdmin {
+                address: Addr::unchecked(PAYEE3),
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::RemoveAdmin {
+                address: Addr::unchecked(OWNER),
+            },
+        )
+        .unwrap();
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetConfig {}).unwrap();
+        let config: ConfigResponse = from_binary(&res).unwrap();
+        assert_eq!(config.owner, Addr::unchecked(OWNER));
+        assert_eq!(
+            config.admins,
+            vec![Addr::unchecked(PAYEE2), Addr::unchecked(PAYEE3)]
+        );
+
+        // Removed admin lost its rights
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::RemoveAdmin {
+                address: Addr::unchecked(PAYEE2),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+    }
+
+    #[test]
+    fn cannot_remove_last_admin() {
+        let mut deps = mock_dependencies(&[]);
+
+        let msg = instantiate_msg(vec![]);
+        let info = mock_info(OWNER, &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::RemoveAdmin {
+                address: Addr::unchecked(PAYEE2),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::AdminNotFound {}));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::RemoveAdmin {
+                address: Addr::unchecked(OWNER),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::CannotRemoveLastAdmin {}));
+    }
+
+    #[test]
+    fn claimable() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        let recipient = Addr::unchecked(PAYEE2);
+        let token = Addr::unchecked("token");
+
+        let payment = |amount: u128, height: u64, token_address: Option<Addr>| Payment {
+            denom: match token_address {
+                Some(_) => String::new(),
+                None => "ujuno".to_string(),
+            },
+            token_address,
+            ..native_payment(
+                recipient.clone(),
+                amount,
+                Expiration::AtHeight(env.block.height + height),
+            )
+        };
+        let msg = instantiate_msg(vec![
+            payment(1, 1, None),
+            payment(2, 1, Some(token.clone())),
+            payment(3, 2, None),
+            Payment {
+                recipient: Addr::unchecked(PAYEE3),
+                ..payment(4, 1, None)
+            },
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let query_claimable = |deps: Deps, env: &Env| -> ClaimableResponse {
+            let msg = QueryMsg::Claimable {
+                recipient: recipient.clone(),
+            };
+            from_binary(&query(deps, env.clone(), msg).unwrap()).unwrap()
+        };
+
+        // Nothing is due yet
+        let res = query_claimable(deps.as_ref(), &env);
+        assert_eq!(res.native, vec![]);
+        assert_eq!(res.cw20, vec![]);
+
+        // First native and cw20 payments are due
+        let mut env = env;
+        env.block.height += 1;
+        let res = query_claimable(deps.as_ref(), &env);
+        assert_eq!(res.native, coins(1, "ujuno"));
+        assert_eq!(
+            res.cw20,
+            vec![Cw20CoinVerified {
+                address: token.clone(),
+                amount: Uint128::new(2),
+            }]
+        );
+
+        // Native payments are summed per denom
+        env.block.height += 1;
+        let res = query_claimable(deps.as_ref(), &env);
+        assert_eq!(res.native, coins(4, "ujuno"));
+        assert_eq!(res.cw20.len(), 1);
+
+        // Paid payments are no longer claimable
+        for id in 1..=3u64 {
+            payments()
+                .update(deps.as_mut().storage, id.into(), |p| -> StdResult<_> {
+                    Ok(PaymentState {
+                        paid: true,
+                        ..p.unwrap()
+                    })
+                })
+                .unwrap();
+        }
+        let res = query_claimable(deps.as_ref(), &env);
+        assert_eq!(res.native, vec![]);
+        assert_eq!(res.cw20, vec![]);
+    }
+
+    #[test]
+    fn next_id_overflow() {
+        let mut deps = mock_dependencies(&[]);
+
+        PAYMENT_COUNT
+            .save(deps.as_mut().storage, &u64::MAX)
+            .unwrap();
+        let err = next_id(deps.as_mut().storage).unwrap_err();
+        assert!(matches!(err, ContractError::Overflow {}));
+    }
+
+    #[test]
+    fn next_id_collision() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 1, Expiration::AtHeight(env.block.height + 1));
+        let msg = instantiate_msg(vec![payment.clone(), payment]);
+        instantiate(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap();
+
+        // Counter rewound below the stored ids
+        PAYMENT_COUNT.save(deps.as_mut().storage, &1).unwrap();
+        let err = next_id(deps.as_mut().storage).unwrap_err();
+        assert!(matches!(err, ContractError::IdCollision {}));
+        assert_eq!(PAYMENT_COUNT.load(deps.as_ref().storage).unwrap(), 1);
+    }
+
+    #[test]
+    fn migrate_payment_count_key() {
+        let mut deps = mock_dependencies(&[]);
+
+        // Simulate state written before the counter key was renamed
+        LEGACY_PAYMENT_COUNT
+            .save(deps.as_mut().storage, &7)
+            .unwrap();
+
+        let msg = MigrateMsg {
+            owner: Some(Addr::unchecked(OWNER)),
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(
+            LEGACY_PAYMENT_COUNT
+                .may_load(deps.as_ref().storage)
+                .unwrap(),
+            None
+        );
+        assert_eq!(PAYMENT_COUNT.load(deps.as_ref().storage).unwrap(), 7);
+        assert_eq!(next_id(deps.as_mut().storage).unwrap(), 8);
+
+        // Migrating again leaves the counter untouched
+        migrate(deps.as_mut(), mock_env(), MigrateMsg { owner: None }).unwrap();
+        assert_eq!(PAYMENT_COUNT.load(deps.as_ref().storage).unwrap(), 8);
+
+        let version = cw2::get_contract_version(deps.as_ref().storage).unwrap();
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn migrate_payment_indexes() {
+        let mut deps = mock_dependencies(&coins(5, "ujuno"));
+        let env = mock_env();
+
+        // State of the first release: a plain map of payments without any of
+        // the fields added since, the counter under its old key and no config
+        const UNINDEXED_PAYMENTS: cw_storage_plus::Map<cw_storage_plus::U64Key, PaymentState> =
+            cw_storage_plus::Map::new("payments");
+        for (id, paid) in [(1u64, true), (2, false)] {
+            let raw = format!(
+                r#"{{"payment":{{"recipient":"{}","amount":"5","denom":"ujuno","token_address":null,"time":{{"at_height":{}}}}},"paid":{},"id":{}}}"#,
+                PAYEE2, env.block.height, paid, id
+            );
+            deps.storage
+                .set(&UNINDEXED_PAYMENTS.key(id.into()), raw.as_bytes());
+        }
+        deps.storage.set(b"proposal_count", b"2");
+
+        let claimable = |deps: &cosmwasm_std::OwnedDeps<_, _, _>| -> ClaimableResponse {
+            let msg = QueryMsg::Claimable {
+                recipient: Addr::unchecked(PAYEE2),
+            };
+            from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap()
+        };
+        // Nothing is indexed by recipient yet
+        assert!(payments_of(deps.as_ref().storage, &Addr::unchecked(PAYEE2))
+            .unwrap()
+            .is_empty());
+
+        // Without a config there is nobody to own the contract
+        let err = migrate(deps.as_mut(), env.clone(), MigrateMsg { owner: None }).unwrap_err();
+        assert!(matches!(err, ContractError::MissingOwner {}));
+
+        let msg = MigrateMsg {
+            owner: Some(Addr::unchecked(OWNER)),
+        };
+        migrate(deps.as_mut(), env.clone(), msg).unwrap();
+        assert_eq!(claimable(&deps).native, coins(5, "ujuno"));
+        let msg = QueryMsg::GetPaymentsByStatus {
+            status: PaymentStatus::Paid,
+        };
+        let res: PaymentsResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+        assert_eq!(
+            res.payments.iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec![1]
+        );
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.owner, Addr::unchecked(OWNER));
+        assert_eq!(config.admins, vec![Addr::unchecked(OWNER)]);
+
+        // Migrating again leaves a single index entry per payment
+        migrate(deps.as_mut(), env.clone(), MigrateMsg { owner: None }).unwrap();
+        assert_eq!(
+            payments_of(deps.as_ref().storage, &Addr::unchecked(PAYEE2))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // The migrated contract executes and pays out the unpaid payment
+        let msg = ExecuteMsg::Pay { limit: None };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(claimable(&deps).native, vec![]);
+        assert_eq!(next_id(deps.as_mut().storage).unwrap(), 3);
+    }
+
+    #[test]
+    fn payout_start() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let msg = instantiate_msg(vec![native_payment(PAYEE2, 1, Expiration::AtHeight(1))]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let update = ExecuteMsg::UpdateConfig {
+            payout_start: Some(Update::Set(Expiration::AtHeight(env.block.height + 10))),
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: None,
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+
+        // Only admins can update config
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE2, &[]),
+            update.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update).unwrap();
+
+        // Payment is due but payouts have not started
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::PayoutsNotStarted {}));
+
+        env.block.height += 10;
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn recurring_payment() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let start = env.block.height + 1;
+
+        let msg = instantiate_msg(vec![Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(10),
+                installments: 4,
+            }),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(start))
+        }]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let mut pay = |env: &Env| -> Response {
+            execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(PAYEE3, &[]),
+                ExecuteMsg::Pay { limit: None },
+            )
+            .unwrap()
+        };
+        let sent_amount = |res: &Response| -> u128 {
+            match &res.messages[..] {
+                [] => 0,
+                [msg] => match &msg.msg {
+                    CosmosMsg::Bank(cosmwasm_std::BankMsg::Send { amount, .. }) => {
+                        amount[0].amount.u128()
+                    }
+                    _ => panic!("unexpected message"),
+                },
+                _ => panic!("expected a single message"),
+            }
+        };
+
+        // Nothing before the first installment
+        assert_eq!(sent_amount(&pay(&env)), 0);
+
+        // First installment
+        env.block.height = start;
+        assert_eq!(sent_amount(&pay(&env)), 10);
+        assert_eq!(sent_amount(&pay(&env)), 0);
+
+        // Skipping ahead pays every matured installment at once
+        env.block.height = start + 25;
+        assert_eq!(sent_amount(&pay(&env)), 20);
+
+        // Final installment completes the payment
+        env.block.height = start + 30;
+        assert_eq!(sent_amount(&pay(&env)), 10);
+        env.block.height = start + 100;
+        assert_eq!(sent_amount(&pay(&env)), 0);
+
+        let res = query(deps.as_ref(), env, QueryMsg::GetPayments {}).unwrap();
+        let value: PaymentsResponse = from_binary(&res).unwrap();
+        assert!(value.payments[0].paid);
+        assert_eq!(value.payments[0].installments_paid, 4);
+    }
+
+    #[test]
+    fn invalid_recurrence() {
+        let mut deps = mock_dependencies(&[]);
+
+        let payment = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Time(10),
+                installments: 4,
+            }),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(1))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidRecurrence {}));
+    }
+
+    #[test]
+    fn stop_payment() {
+        let mut deps = mock_dependencies(&[]);
+        let mut env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1));
+        let msg = instantiate_msg(vec![payment.clone(), payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Only admins can stop payments
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE2, &[]),
+            ExecuteMsg::StopPayment {
+                id: 1,
+                refund: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // Refund defaults to true and sends the amount back to the owner
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::StopPayment {
+                id: 1,
+                refund: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: OWNER.to_string(),
+                amount: coins(10, "ujuno"),
+            })
+        );
+
+        // Without refund the payment is only marked stopped
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::StopPayment {
+                id: 2,
+                refund: Some(false),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        // Stopped payments cannot be stopped again
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::StopPayment {
+                id: 2,
+                refund: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotActive {}));
+
+        // Unknown ids are reported in the error
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::StopPayment {
+                id: 42,
+                refund: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotFound { id: 42 }));
+        assert_eq!(err.to_string(), "Payment 42 not found");
+
+        // Stopped payments are never paid
+        env.block.height += 1;
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 0);
+    }
+
+    #[test]
+    fn stop_after_active_from() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = Payment {
+            active_from: Some(Expiration::AtHeight(env.block.height + 5)),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 10))
+        };
+        let msg = instantiate_msg(vec![payment.clone(), payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let stop = |id: u64| ExecuteMsg::StopPayment {
+            id,
+            refund: Some(true),
+        };
+        // Before active_from the payment is refunded as usual
+        let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), stop(1)).unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        env.block.height += 5;
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), stop(2)).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentActive {}));
+    }
+
+    #[test]
+    fn refund_attributes() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 10));
+        let msg = instantiate_msg(vec![payment.clone(), payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let refunds = |res: Response| -> Vec<String> {
+            res.attributes
+                .into_iter()
+                .filter(|a| a.key == "refund")
+                .map(|a| a.value)
+                .collect()
+        };
+        let stop = |id: u64, refund: bool| ExecuteMsg::StopPayment {
+            id,
+            refund: Some(refund),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            stop(2, true),
+        )
+        .unwrap();
+        assert_eq!(refunds(res), vec!["payment_id=2,reason=stop"]);
+
+        // Nothing is sent without a refund, so nothing is labelled
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), stop(1, false)).unwrap();
+        assert_eq!(refunds(res), Vec::<String>::new());
+    }
+
+    #[test]
+    fn stopped_payment_never_paid() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1));
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Stopped and expired, as if stopped after Pay selected it
+        env.block.height += 1;
+        let selected = select_payouts(deps.as_ref(), &env, None, None).unwrap();
+        assert_eq!(selected.to_be_paid.len(), 1);
+        let stopped = PaymentState {
+            stopped: true,
+            ..selected.to_be_paid[0].0.clone()
+        };
+        payments()
+            .save(deps.as_mut().storage, 1u64.into(), &stopped)
+            .unwrap();
+
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        let res = send_payouts(deps.as_mut(), &env, &config, selected, Response::new()).unwrap();
+        assert!(res.messages.is_empty());
+
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn stop_vested_payment() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let msg = instantiate_msg(vec![native_payment(
+            PAYEE2,
+            10,
+            Expiration::AtHeight(env.block.height + 1),
+        )]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Payment expires without being swept by Pay
+        env.block.height += 1;
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::StopPayment {
+                id: 1,
+                refund: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::AlreadyVested {}));
+
+        // Recipient can still be paid
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn payments_by_status() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = |height: u64| {
+            native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + height))
+        };
+        let msg = instantiate_msg(vec![payment(1), payment(2), payment(3), payment(3)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Payment 1 is paid, 2 is claimable, 3 is stopped and 4 is pending
+        env.block.height += 1;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::StopPayment {
+                id: 3,
+                refund: None,
+            },
+        )
+        .unwrap();
+        env.block.height += 1;
+
+        let ids = |status: PaymentStatus| -> Vec<u64> {
+            let msg = QueryMsg::GetPaymentsByStatus { status };
+            let res: PaymentsResponse =
+                from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+            res.payments.iter().map(|p| p.id).collect()
+        };
+        assert_eq!(ids(PaymentStatus::Paid), vec![1]);
+        assert_eq!(ids(PaymentStatus::Claimable), vec![2]);
+        assert_eq!(ids(PaymentStatus::Stopped), vec![3]);
+        assert_eq!(ids(PaymentStatus::Pending), vec![4]);
+    }
+
+    #[test]
+    fn instantiate_with_funds() {
+        let mut deps = mock_dependencies(&[]);
+
+        let msg = instantiate_msg(vec![]);
+        let info = mock_info(OWNER, &[coin(10, "ujuno"), coin(5, "uatom")]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("method", "instantiate"),
+                attr("owner", OWNER),
+                attr("num_payments", "0"),
+                attr("funded", "10ujuno,5uatom")
+            ]
+        );
+
+        // Without funds the attribute is omitted
+        let msg = instantiate_msg(vec![]);
+        let res = instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("method", "instantiate"),
+                attr("owner", OWNER),
+                attr("num_payments", "0")
+            ]
+        );
+    }
+
+    #[test]
+    fn instantiate_attributes() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 4, Expiration::AtHeight(env.block.height + 10));
+        let msg = instantiate_msg(vec![
+            payment.clone(),
+            Payment {
+                amount: Uint128::new(3),
+                recurrence: Some(Recurrence {
+                    interval: Duration::Height(10),
+                    installments: 2,
+                }),
+                extra_coins: Some(coins(1, "uatom")),
+                ..payment.clone()
+            },
+            Payment {
+                denom: String::new(),
+                token_address: Some(Addr::unchecked("token")),
+                ..payment
+            },
+        ]);
+        let res = instantiate(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("method", "instantiate"),
+                attr("owner", OWNER),
+                attr("num_payments", "3"),
+                attr("committed", "10ujuno,2uatom"),
+                attr("committed_cw20", "4token")
+            ]
+        );
+    }
+
+    #[test]
+    fn contract_version() {
+        let mut deps = mock_dependencies(&[]);
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let version = cw2::get_contract_version(deps.as_ref().storage).unwrap();
+        assert_eq!(version.contract, "cw-vesting");
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn partial_funding() {
+        let mut app = mock_app();
+
+        let (owner, _funder, _payee2, _payee3) = get_accounts();
+
+        let current_height = app.block_info().height;
+
+        let denom = String::from("ujuno");
+        let payment = |amount: u128| Payment {
+            denom: denom.clone(),
+            ..native_payment(
+                owner.clone(),
+                amount,
+                Expiration::AtHeight(current_height + 1),
+            )
+        };
+        let payments = vec![payment(1), payment(2), payment(5)];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+
+        // Fund vest contract with less than is due
+        app.init_bank_balance(&vest_addr, vec![coin(4, denom.clone())])
+            .unwrap();
+
+        let owner_balance = |app: &App<Empty>| {
+            app.wrap()
+                .query_balance(owner.clone(), denom.clone())
+                .unwrap()
+                .amount
+                .u128()
+        };
+        let initial_balance = owner_balance(&app);
+
+        // Earliest payments are covered and the last one stays pending
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 3);
+
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let paid: Vec<bool> = res.payments.iter().map(|p| p.paid).collect();
+        assert_eq!(paid, vec![true, true, false]);
+
+        // Topping up pays the remaining payment
+        app.init_bank_balance(&vest_addr, vec![coin(5, denom.clone())])
+            .unwrap();
+        app.execute_contract(
+            _payee3,
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 8);
+    }
+
+    #[test]
+    fn schedule_lock() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let add = ExecuteMsg::AddPayments {
+            schedule: vec![native_payment(
+                PAYEE2,
+                10,
+                Expiration::AtHeight(env.block.height + 1),
+            )],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            add.clone(),
+        )
+        .unwrap();
+
+        let update = |locked: bool| ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: Some(locked),
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: None,
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            update(true),
+        )
+        .unwrap();
+
+        // Adding payments fails once locked
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            add.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ScheduleLocked {}));
+
+        // Lock cannot be undone
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            update(false),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ScheduleLocked {}));
+
+        // Other config updates leave the lock in place
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::UpdateConfig {
+                payout_start: None,
+                schedule_locked: None,
+                payers: None,
+                ibc_timeout_seconds: None,
+                fee: None,
+                min_payout: None,
+                stop_grace: None,
+                payout_order: None,
+                expected_version: None,
+                keeper_reward: None,
+                owner: None,
+                enabled: None,
+            },
+        )
+        .unwrap();
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::GetConfig {}).unwrap();
+        let config: ConfigResponse = from_binary(&res).unwrap();
+        assert!(config.schedule_locked);
+
+        let res = query(deps.as_ref(), env, QueryMsg::GetPayments {}).unwrap();
+        let value: PaymentsResponse = from_binary(&res).unwrap();
+        assert_eq!(value.payments.len(), 1);
+    }
+
+    #[test]
+    fn stop_payment_data() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        let token = Addr::unchecked("token");
+
+        let payment = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(1),
+                installments: 3,
+            }),
+            ..cw20_payment(
+                PAYEE2,
+                10,
+                &token,
+                Expiration::AtHeight(env.block.height + 1),
+            )
+        };
+        let msg = instantiate_msg(vec![payment.clone(), payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let stop = |deps: DepsMut, id: u64, refund: bool| -> StopPaymentResponse {
+            let msg = ExecuteMsg::StopPayment {
+                id,
+                refund: Some(refund),
+            };
+            let res = execute(deps, env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+            from_binary(&res.data.unwrap()).unwrap()
+        };
+
+        assert_eq!(
+            stop(deps.as_mut(), 1, true),
+            StopPaymentResponse {
+                id: 1,
+                refunded: Uint128::new(30),
+                denom: String::new(),
+                token_address: Some(token.clone()),
+            }
+        );
+        assert_eq!(
+            stop(deps.as_mut(), 2, false),
+            StopPaymentResponse {
+                id: 2,
+                refunded: Uint128::zero(),
+                denom: String::new(),
+                token_address: Some(token),
+            }
+        );
+    }
+
+    #[test]
+    fn preview_stop() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(1),
+                installments: 3,
+            }),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        env.block.height += 1;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+
+        let preview: StopPaymentResponse = from_binary(
+            &query(deps.as_ref(), env.clone(), QueryMsg::PreviewStop { id: 1 }).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(preview.refunded, Uint128::new(20));
+
+        let msg = ExecuteMsg::StopPayment {
+            id: 1,
+            refund: Some(true),
+        };
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: OWNER.to_string(),
+                amount: coins(preview.refunded.u128(), preview.denom.clone()),
+            })
+        );
+        assert_eq!(
+            from_binary::<StopPaymentResponse>(&res.data.unwrap()).unwrap(),
+            preview
+        );
+    }
+
+    #[test]
+    fn add_linear_schedule() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        let height = env.block.height;
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let linear = |total: u128, end: Expiration| ExecuteMsg::AddLinearSchedule {
+            recipient: Addr::unchecked(PAYEE2),
+            total: Uint128::new(total),
+            denom: "ujuno".to_string(),
+            token_address: None,
+            start: Expiration::AtHeight(height + 10),
+            end,
+            num_installments: 4,
+        };
+        for msg in [
+            linear(3, Expiration::AtHeight(height + 40)),
+            linear(10, Expiration::AtHeight(height + 5)),
+            linear(10, Expiration::AtTime(env.block.time)),
+        ] {
+            let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidLinearSchedule {}));
+        }
+        let msg = ExecuteMsg::AddLinearSchedule {
+            recipient: Addr::unchecked(PAYEE2),
+            total: Uint128::new(u128::MAX),
+            denom: "ujuno".to_string(),
+            token_address: None,
+            start: Expiration::AtHeight(height + 10),
+            end: Expiration::AtHeight(height + 40),
+            num_installments: u32::MAX,
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::TooManyPayments { .. }));
+
+        let msg = linear(10, Expiration::AtHeight(height + 40));
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        let res: PaymentsResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::GetPayments {}).unwrap()).unwrap();
+        let installments: Vec<(u128, Expiration)> = res
+            .payments
+            .iter()
+            .map(|p| (p.payment.amount.u128(), p.payment.time))
+            .collect();
+        assert_eq!(
+            installments,
+            vec![
+                (2, Expiration::AtHeight(height + 10)),
+                (2, Expiration::AtHeight(height + 20)),
+                (2, Expiration::AtHeight(height + 30)),
+                (4, Expiration::AtHeight(height + 40)),
+            ]
+        );
+        let total: u128 = installments.iter().map(|(amount, _)| amount).sum();
+        assert_eq!(total, 10);
+    }
+
+    #[test]
+    fn add_payments_ids() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1));
+        let msg = instantiate_msg(vec![payment.clone()]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::AddPayments {
+            schedule: vec![payment.clone(), payment],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![attr("method", "add_payments"), attr("count", "2")]
+        );
+        let data: AddPaymentsResponse = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(data.ids, vec![2, 3]);
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        let mut deps = mock_dependencies(&[]);
+
+        let payment = native_payment("x", 10, Expiration::AtHeight(1));
+        let msg = instantiate_msg(vec![payment.clone()]);
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)));
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Token addresses are validated as well
+        let msg = ExecuteMsg::AddPayments {
+            schedule: vec![Payment {
+                recipient: Addr::unchecked(PAYEE2),
+                token_address: Some(Addr::unchecked("x")),
+                ..payment
+            }],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)));
+    }
+
+    #[test]
+    fn due_payments() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        let height = env.block.height;
+
+        let payment =
+            |offset: u64| native_payment(PAYEE2, 10, Expiration::AtHeight(height + offset));
+        let msg = instantiate_msg(vec![payment(1), payment(2), payment(3)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let due = |at: Option<Expiration>| -> Vec<u64> {
+            let msg = QueryMsg::DuePayments { at };
+            let res: PaymentsResponse =
+                from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+            res.payments.iter().map(|p| p.id).collect()
+        };
+        assert_eq!(due(None), Vec::<u64>::new());
+        assert_eq!(due(Some(Expiration::AtHeight(height + 2))), vec![1, 2]);
+        assert_eq!(due(Some(Expiration::AtHeight(height + 3))), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn due_count() {
+        let mut deps = mock_dependencies(&[]);
+        let mut env = mock_env();
+        let height = env.block.height;
+
+        let payment =
+            |offset: u64| native_payment(PAYEE2, 10, Expiration::AtHeight(height + offset));
+        let msg = instantiate_msg(vec![payment(1), payment(2), payment(2), payment(5)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        env.block.height = height + 2;
+        let res: DueCountResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::DueCount {}).unwrap()).unwrap();
+        assert_eq!(res.count, 3);
+    }
+
+    #[test]
+    fn payment_memo() {
+        let mut deps = mock_dependencies(&coins(10, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = Payment {
+            memo: Some("q1 grant".to_string()),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1))
+        };
+
+        // Memo length is bounded
+        let msg = instantiate_msg(vec![Payment {
+            memo: Some("a".repeat(MAX_MEMO_LENGTH + 1)),
+            ..payment.clone()
+        }]);
+        let err = instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::MemoTooLong { .. }));
+
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::GetPayments {}).unwrap();
+        let value: PaymentsResponse = from_binary(&res).unwrap();
+        assert_eq!(value.payments[0].payment.memo, Some("q1 grant".to_string()));
+
+        env.block.height += 1;
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("method", "pay"),
+                attr("paid_id", "1"),
+                attr("memo", "q1 grant")
+            ]
+        );
+    }
+
+    #[test]
+    fn failing_payout_is_isolated() {
+        let mut app = mock_app();
+
+        let (owner, funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+        let failing_id = app.store_code(contract_failing_cw20());
+        let failing_addr = app
+            .instantiate_contract(failing_id, owner.clone(), &Empty {}, &[], "fail", None)
+            .unwrap();
+
+        let current_height = app.block_info().height;
+        let payment = |amount: u128, token_address: Addr| {
+            cw20_payment(
+                owner.clone(),
+                amount,
+                &token_address,
+                Expiration::AtHeight(current_height + 1),
+            )
+        };
+        let payments = vec![
+            payment(1, cw20_addr.clone()),
+            payment(2, failing_addr),
+            payment(3, cw20_addr.clone()),
+        ];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr.clone(),
+            funder,
+            Uint128::new(4),
+        );
+
+        let owner_balance = |app: &App<Empty>| cw20.balance(app, owner.clone()).unwrap().u128();
+        let initial_balance = owner_balance(&app);
+
+        app.update_block(next_block);
+        app.execute_contract(
+            _payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(owner_balance(&app), initial_balance + 4);
+
+        // Only the failing payment is left unpaid
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let paid: Vec<bool> = res.payments.iter().map(|p| p.paid).collect();
+        assert_eq!(paid, vec![true, false, true]);
+    }
+
+    #[test]
+    fn failing_payout_is_quarantined() {
+        let mut app = mock_app();
+
+        let (owner, _funder, _payee2, payee3) = get_accounts();
+
+        let failing_id = app.store_code(contract_failing_cw20());
+        let failing_addr = app
+            .instantiate_contract(failing_id, owner.clone(), &Empty {}, &[], "fail", None)
+            .unwrap();
+
+        let current_height = app.block_info().height;
+        let payment = cw20_payment(
+            owner,
+            1,
+            &failing_addr,
+            Expiration::AtHeight(current_height + 1),
+        );
+        let vest_addr = instantiate_vest(&mut app, vec![payment]);
+        app.update_block(next_block);
+
+        for attempt in 1..=MAX_PAYOUT_FAILURES {
+            let res = app
+                .execute_contract(
+                    payee3.clone(),
+                    vest_addr.clone(),
+                    &ExecuteMsg::Pay { limit: None },
+                    &[],
+                )
+                .unwrap();
+            let quarantined = res.events.iter().any(|e| {
+                e.attributes
+                    .iter()
+                    .any(|a| a.key == "quarantined" && a.value == "true")
+            });
+            assert_eq!(quarantined, attempt == MAX_PAYOUT_FAILURES);
+        }
+
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let p = &res.payments[0];
+        assert!(p.paused && !p.paid);
+        assert_eq!(p.fail_count, MAX_PAYOUT_FAILURES);
+    }
+
+    #[test]
+    fn conditional_payment() {
+        let mut app = mock_app();
+
+        let (owner, funder, payee2, payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+        let oracle_id = app.store_code(contract_oracle());
+        let oracle_addr = app
+            .instantiate_contract(oracle_id, owner.clone(), &Empty {}, &[], "oracle", None)
+            .unwrap();
+
+        let current_height = app.block_info().height;
+        let payment = Payment {
+            condition: Some(Condition {
+                contract: oracle_addr.clone(),
+                query: to_binary(&Empty {}).unwrap(),
+                expected: to_binary(&true).unwrap(),
+            }),
+            ..cw20_payment(
+                payee2.clone(),
+                5,
+                &cw20_addr,
+                Expiration::AtHeight(current_height + 1),
+            )
+        };
+        let vest_addr = instantiate_vest(&mut app, vec![payment]);
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr,
+            funder,
+            Uint128::new(5),
+        );
+        app.update_block(next_block);
+
+        let pay = |app: &mut App<Empty>| {
+            app.execute_contract(
+                payee3.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Pay { limit: None },
+                &[],
+            )
+            .unwrap();
+        };
+        let balance = |app: &App<Empty>| cw20.balance(app, payee2.clone()).unwrap().u128();
+        let initial_balance = balance(&app);
+
+        // The oracle answers false, so the payment waits
+        pay(&mut app);
+        assert_eq!(balance(&app), initial_balance);
+
+        app.execute_contract(owner, oracle_addr, &true, &[])
+            .unwrap();
+        pay(&mut app);
+        assert_eq!(balance(&app), initial_balance + 5);
+    }
+
+    #[test]
+    fn stop_with_unmet_condition() {
+        let mut app = mock_app();
+
+        let (owner, _funder, payee2, _payee3) = get_accounts();
+        let oracle_id = app.store_code(contract_oracle());
+        let oracle_addr = app
+            .instantiate_contract(oracle_id, owner.clone(), &Empty {}, &[], "oracle", None)
+            .unwrap();
+
+        let current_height = app.block_info().height;
+        let payment = Payment {
+            condition: Some(Condition {
+                contract: oracle_addr,
+                query: to_binary(&Empty {}).unwrap(),
+                expected: to_binary(&true).unwrap(),
+            }),
+            ..native_payment(payee2.clone(), 5, Expiration::AtHeight(current_height + 1))
+        };
+        let vest_addr = instantiate_vest(&mut app, vec![payment.clone(), payment]);
+        app.init_bank_balance(&vest_addr, coins(10, "ujuno"))
+            .unwrap();
+        app.update_block(next_block);
+
+        // Both payments are due but held back by the oracle, so neither blocks
+        // a stop and their due amounts go back to the owner
+        let msg = ExecuteMsg::StopPayment {
+            id: 1,
+            refund: Some(true),
+        };
+        app.execute_contract(owner.clone(), vest_addr.clone(), &msg, &[])
+            .unwrap();
+        app.execute_contract(
+            owner.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Terminate {},
+            &[],
+        )
+        .unwrap();
+
+        let native = |addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(native(&owner), 10);
+        assert_eq!(native(&payee2), 0);
+        assert_eq!(native(&vest_addr), 0);
+    }
+
+    #[test]
+    fn failing_native_payout_is_isolated() {
+        let mut app = mock_app();
+
+        let (owner, _funder, payee2, payee3) = get_accounts();
+
+        let current_height = app.block_info().height;
+        let payment = native_payment(payee2.clone(), 1, Expiration::AtHeight(current_height + 1));
+        let payments = vec![
+            payment.clone(),
+            // payee3 is not a contract, so the callback can't be delivered
+            Payment {
+                recipient: payee3.clone(),
+                amount: Uint128::new(2),
+                on_receive: Some(to_binary(&ReceiverMsg::Stake {}).unwrap()),
+                ..payment.clone()
+            },
+            Payment {
+                amount: Uint128::new(3),
+                ..payment
+            },
+        ];
+        let vest_addr = instantiate_vest(&mut app, payments);
+        app.init_bank_balance(&vest_addr, coins(6, "ujuno"))
+            .unwrap();
+
+        app.update_block(next_block);
+        app.execute_contract(
+            owner,
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        let native = |addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(native(&payee2), 4);
+        assert_eq!(native(&payee3), 0);
+        assert_eq!(native(&vest_addr), 2);
+
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let paid: Vec<bool> = res.payments.iter().map(|p| p.paid).collect();
+        assert_eq!(paid, vec![true, false, true]);
+    }
+
+    #[test]
+    fn keeper_reward_for_pay() {
+        let mut app = mock_app();
+
+        let (owner, _funder, payee2, payee3) = get_accounts();
+
+        let current_height = app.block_info().height;
+        let payment = |offset: u64| {
+            native_payment(
+                payee2.clone(),
+                10,
+                Expiration::AtHeight(current_height + offset),
+            )
+        };
+        let vest_addr = instantiate_vest(&mut app, vec![payment(1), payment(2)]);
+        // One more than the payments are owed, enough for a single reward
+        app.init_bank_balance(&vest_addr, coins(21, "ujuno"))
+            .unwrap();
+        app.execute_contract(
+            owner,
+            vest_addr.clone(),
+            &ExecuteMsg::UpdateConfig {
+                payout_start: None,
+                schedule_locked: None,
+                payers: None,
+                ibc_timeout_seconds: None,
+                fee: None,
+                min_payout: None,
+                stop_grace: None,
+                payout_order: None,
+                keeper_reward: Some(Update::Set(coin(1, "ujuno"))),
+                expected_version: None,
+                owner: None,
+                enabled: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        let pay = |app: &mut App| {
+            app.execute_contract(
+                payee3.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Pay { limit: None },
+                &[],
+            )
+            .unwrap();
+        };
+        let native = |app: &App, addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+
+        // Nothing is due, so nothing is earned
+        pay(&mut app);
+        assert_eq!(native(&app, &payee3), 0);
+
+        app.update_block(next_block);
+        pay(&mut app);
+        assert_eq!(native(&app, &payee2), 10);
+        assert_eq!(native(&app, &payee3), 1);
+
+        // The rest of the balance is owed to the second payment
+        app.update_block(next_block);
+        pay(&mut app);
+        assert_eq!(native(&app, &payee2), 20);
+        assert_eq!(native(&app, &payee3), 1);
+        assert_eq!(native(&app, &vest_addr), 0);
+    }
+
+    #[test]
+    fn on_receive_callback() {
+        let mut app = mock_app();
+
+        let (owner, funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+        let receiver_id = app.store_code(contract_receiver());
+        let receiver = app
+            .instantiate_contract(receiver_id, owner, &Empty {}, &[], "receiver", None)
+            .unwrap();
+
+        let current_height = app.block_info().height;
+        let denom = String::from("ujuno");
+        let payment = Payment {
+            denom: denom.clone(),
+            on_receive: Some(to_binary(&ReceiverMsg::Stake {}).unwrap()),
+            ..native_payment(
+                receiver.clone(),
+                1,
+                Expiration::AtHeight(current_height + 1),
+            )
+        };
+        let payments = vec![
+            payment.clone(),
+            Payment {
+                denom: String::new(),
+                token_address: Some(cw20_addr.clone()),
+                amount: Uint128::new(2),
+                ..payment
+            },
+        ];
+
+        let vest_addr = instantiate_vest(&mut app, payments);
+        app.init_bank_balance(&vest_addr, vec![coin(1, denom.clone())])
+            .unwrap();
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr,
+            funder,
+            Uint128::new(2),
+        );
+
+        app.update_block(next_block);
+        app.execute_contract(_payee3, vest_addr, &ExecuteMsg::Pay { limit: None }, &[])
+            .unwrap();
+
+        // Both payouts reached the receiver and fired its callback
+        let received: u32 = app.wrap().query_wasm_smart(&receiver, &Empty {}).unwrap();
+        assert_eq!(received, 2);
+        let native = app.wrap().query_balance(&receiver, denom).unwrap();
+        assert_eq!(native.amount.u128(), 1);
+        assert_eq!(cw20.balance(&app, receiver).unwrap().u128(), 2);
+    }
+
+    #[test]
+    fn next_unlock() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let height = env.block.height;
+
+        let payment =
+            |offset: u64| native_payment(PAYEE2, 10, Expiration::AtHeight(height + offset));
+        let msg = instantiate_msg(vec![
+            payment(5),
+            payment(2),
+            Payment {
+                recurrence: Some(Recurrence {
+                    interval: Duration::Height(2),
+                    installments: 2,
+                }),
+                ..payment(3)
+            },
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let next_unlock = |deps: Deps, env: &Env| -> Option<Expiration> {
+            let res = query(deps, env.clone(), QueryMsg::NextUnlock {}).unwrap();
+            from_binary::<NextUnlockResponse>(&res).unwrap().next_unlock
+        };
+        assert_eq!(
+            next_unlock(deps.as_ref(), &env),
+            Some(Expiration::AtHeight(height + 2))
+        );
+
+        // Second installment of the recurring payment comes next
+        env.block.height = height + 3;
+        assert_eq!(
+            next_unlock(deps.as_ref(), &env),
+            Some(Expiration::AtHeight(height + 5))
+        );
+
+        // Nothing left once everything is paid
+        env.block.height = height + 5;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(next_unlock(deps.as_ref(), &env), None);
+    }
+
+    #[test]
+    fn pay_with_limit() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = native_payment(PAYEE2, 1, Expiration::AtHeight(env.block.height + 1));
+        let msg = instantiate_msg(vec![payment; 100]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        env.block.height += 1;
+
+        // Each call drains the next oldest payments
+        for call in 0..10u64 {
+            let msg = ExecuteMsg::Pay { limit: Some(10) };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+            assert_eq!(res.messages.len(), 10);
+            let ids: Vec<u64> = res.messages.iter().map(|m| m.id).collect();
+            assert_eq!(ids, (call * 10 + 1..=call * 10 + 10).collect::<Vec<u64>>());
+        }
+
+        let msg = ExecuteMsg::Pay { limit: Some(10) };
+        let res = execute(deps.as_mut(), env, mock_info(PAYEE3, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 0);
+    }
+
+    #[test]
+    fn milestone_payment() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let height = env.block.height;
+
+        let milestones = vec![
+            Milestone {
+                time: Expiration::AtHeight(height + 1),
+                percent: 25,
+            },
+            Milestone {
+                time: Expiration::AtHeight(height + 2),
+                percent: 25,
+            },
+            Milestone {
+                time: Expiration::AtHeight(height + 3),
+                percent: 50,
+            },
+        ];
+        let payment = Payment {
+            milestones: Some(milestones.clone()),
+            ..native_payment(PAYEE2, 100, Expiration::AtHeight(height + 1))
+        };
+        let msg = instantiate_msg(
+            // Amount that does not split evenly
+            vec![
+                payment.clone(),
+                Payment {
+                    amount: Uint128::new(10),
+                    ..payment.clone()
+                },
+            ],
+        );
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let mut pay = |env: &Env| -> Vec<u128> {
+            let msg = ExecuteMsg::Pay { limit: None };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+            res.messages
+                .iter()
+                .map(|m| match &m.msg {
+                    CosmosMsg::Bank(cosmwasm_std::BankMsg::Send { amount, .. }) => {
+                        amount[0].amount.u128()
+                    }
+                    _ => panic!("unexpected message"),
+                })
+                .collect()
+        };
+
+        env.block.height = height + 1;
+        assert_eq!(pay(&env), vec![25, 2]);
+        env.block.height = height + 2;
+        assert_eq!(pay(&env), vec![25, 3]);
+        env.block.height = height + 3;
+        assert_eq!(pay(&env), vec![50, 5]);
+        assert_eq!(pay(&env), Vec::<u128>::new());
+
+        // Percentages must sum to 100
+        let mut invalid = milestones;
+        invalid[2].percent = 40;
+        let msg = ExecuteMsg::AddPayments {
+            schedule: vec![Payment {
+                milestones: Some(invalid),
+                ..payment
+            }],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMilestones {}));
+    }
+
+    #[test]
+    fn separate_admin_and_owner() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        const ADMIN: &str = "admin0001";
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1));
+        let msg = InstantiateMsg {
+            admin: Addr::unchecked(ADMIN),
+            ..instantiate_msg(vec![payment])
+        };
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::GetConfig {}).unwrap())
+                .unwrap();
+        assert_eq!(config.owner, Addr::unchecked(OWNER));
+        assert_eq!(config.admins, vec![Addr::unchecked(ADMIN)]);
+
+        // The owner cannot manage the contract
+        let stop = ExecuteMsg::StopPayment {
+            id: 1,
+            refund: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            stop.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // The admin can, and the refund still goes to the owner
+        let res = execute(deps.as_mut(), env, mock_info(ADMIN, &[]), stop).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: OWNER.to_string(),
+                amount: coins(10, "ujuno"),
+            })
+        );
+    }
+
+    #[test]
+    fn multi_denom_payment() {
+        let mut app = mock_app();
+        let (_owner, _funder, payee2, payee3) = get_accounts();
+
+        let payments = vec![Payment {
+            extra_coins: Some(vec![coin(7, "uatom")]),
+            ..native_payment(payee2.clone(), 5, Expiration::AtHeight(1))
+        }];
+        let vest_addr = instantiate_vest(&mut app, payments);
+
+        // Nothing is paid until every denom is covered
+        app.init_bank_balance(&vest_addr, vec![coin(5, "ujuno")])
+            .unwrap();
+        let res = app
+            .execute_contract(
+                payee3.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Pay { limit: None },
+                &[],
+            )
+            .unwrap();
+        assert!(!res.events.iter().any(|e| e.ty == "transfer"));
+
+        app.init_bank_balance(&vest_addr, vec![coin(5, "ujuno"), coin(7, "uatom")])
+            .unwrap();
+        let claimable: ClaimableResponse = app
+            .wrap()
+            .query_wasm_smart(
+                &vest_addr,
+                &QueryMsg::Claimable {
+                    recipient: payee2.clone(),
+                },
+            )
+            .unwrap();
+        assert_eq!(claimable.native, vec![coin(5, "ujuno"), coin(7, "uatom")]);
+
+        app.execute_contract(payee3, vest_addr, &ExecuteMsg::Pay { limit: None }, &[])
+            .unwrap();
+        let balance = |denom: &str| {
+            app.wrap()
+                .query_balance(payee2.clone(), denom)
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(balance("ujuno"), 5);
+        assert_eq!(balance("uatom"), 7);
+
+        // Extra coins must be native and use distinct denoms
+        let invalid = Payment {
+            extra_coins: Some(vec![coin(7, "ujuno")]),
+            ..native_payment(PAYEE2, 5, Expiration::AtHeight(1))
+        };
+        let mut deps = mock_dependencies(&[]);
+        let msg = instantiate_msg(vec![invalid]);
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidExtraCoins {}));
+    }
+
+    #[test]
+    fn restricted_payers() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+        const KEEPER: &str = "keeper0001";
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height));
+        let msg = instantiate_msg(vec![payment.clone(), payment.clone(), payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Anyone can pay by default
+        let pay = ExecuteMsg::Pay { limit: Some(1) };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            pay.clone(),
+        )
+        .unwrap();
+
+        let update = ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: None,
+            payers: Some(Update::Set(vec![Addr::unchecked(KEEPER)])),
+            ibc_timeout_seconds: None,
+            fee: None,
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            pay.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // Listed payers and the owner are still allowed
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(KEEPER, &[]),
+            pay.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), pay).unwrap();
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn payments_count() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = |height: u64| native_payment(PAYEE2, 10, Expiration::AtHeight(height));
+        let msg = instantiate_msg(vec![
+            payment(env.block.height),
+            payment(env.block.height + 1),
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        let msg = ExecuteMsg::AddPayments {
+            schedule: vec![payment(env.block.height), payment(env.block.height + 1)],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let count = |deps: Deps| -> PaymentsCountResponse {
+            from_binary(&query(deps, mock_env(), QueryMsg::GetPaymentsCount {}).unwrap()).unwrap()
+        };
+        let expected = |total, paid, stopped| PaymentsCountResponse {
+            total,
+            paid,
+            stopped,
+        };
+        assert_eq!(count(deps.as_ref()), expected(4, 0, 0));
+
+        let msg = ExecuteMsg::Pay { limit: None };
+        execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+        assert_eq!(count(deps.as_ref()), expected(4, 2, 0));
+
+        let msg = ExecuteMsg::StopPayment {
+            id: 2,
+            refund: Some(false),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(count(deps.as_ref()), expected(4, 2, 1));
+
+        // A failed payout is no longer counted as paid
+        let failed = Reply {
+            id: 3,
+            result: ContractResult::Err("failed".to_string()),
+        };
+        reply(deps.as_mut(), env, failed).unwrap();
+        assert_eq!(count(deps.as_ref()), expected(4, 1, 1));
+    }
+
+    #[test]
+    fn enforce_order() {
+        let mut failed = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = |height: u64| native_payment(PAYEE2, 10, Expiration::AtHeight(height));
+
+        let msg = InstantiateMsg {
+            enforce_order: true,
+            ..instantiate_msg(vec![payment(10), payment(5)])
+        };
+        let err =
+            instantiate(failed.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::OutOfOrder {}));
+
+        let mut deps = mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            enforce_order: true,
+            ..instantiate_msg(vec![payment(5), payment(10), payment(10)])
+        };
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Additions are ordered against the existing schedule
+        let add = |schedule| ExecuteMsg::AddPayments {
+            schedule,
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            add(vec![payment(9)]),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::OutOfOrder {}));
+
+        let time_payment = Payment {
+            time: Expiration::AtTime(env.block.time),
+            ..payment(0)
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            add(vec![time_payment]),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::OutOfOrder {}));
+
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info(OWNER, &[]),
+            add(vec![payment(10), payment(11)]),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn revoke() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(1),
+                installments: 3,
+            }),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1))
+        };
+        let msg = instantiate_msg(vec![payment.clone(), payment.clone(), payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let revoke = |deps: DepsMut, env: &Env, id: u64| {
+            execute(
+                deps,
+                env.clone(),
+                mock_info(OWNER, &[]),
+                ExecuteMsg::Revoke { id },
+            )
+        };
+        let refunded = |res: &Response| -> StopPaymentResponse {
+            from_binary(res.data.as_ref().unwrap()).unwrap()
+        };
+        let payment = |deps: Deps, id: u64| payments().load(deps.storage, id.into()).unwrap();
+
+        // Before the first unlock everything goes back
+        let res = revoke(deps.as_mut(), &env, 1).unwrap();
+        assert_eq!(refunded(&res).refunded, Uint128::new(30));
+        assert!(payment(deps.as_ref(), 1).stopped);
+
+        // Two of three installments have unlocked, only the last is refunded
+        env.block.height += 2;
+        let res = revoke(deps.as_mut(), &env, 2).unwrap();
+        assert_eq!(refunded(&res).refunded, Uint128::new(10));
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: PAYEE2.to_string(),
+                amount: coins(20, "ujuno"),
+            })
+        );
+        let revoked = payment(deps.as_ref(), 2);
+        assert!(revoked.paid);
+        assert_eq!(revoked.installments_paid, 2);
+
+        // Nothing is left to refund once fully unlocked
+        env.block.height += 1;
+        let err = revoke(deps.as_mut(), &env, 3).unwrap_err();
+        assert!(matches!(err, ContractError::AlreadyVested {}));
+    }
+
+    #[test]
+    fn clawback() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        const ADMIN: &str = "admin0001";
+
+        let payment = Payment {
+            expiry: Some(Expiration::AtHeight(env.block.height + 10)),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height))
+        };
+        let msg = InstantiateMsg {
+            admin: Addr::unchecked(ADMIN),
+            ..instantiate_msg(vec![
+                payment.clone(),
+                Payment {
+                    expiry: None,
+                    ..payment
+                },
+            ])
+        };
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let clawback = |deps: DepsMut, env: &Env, sender: &str, id: u64| {
+            execute(
+                deps,
+                env.clone(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Clawback { id },
+            )
+        };
+
+        // Unpaid funds stay with the recipient before the deadline
+        let err = clawback(deps.as_mut(), &env, OWNER, 1).unwrap_err();
+        assert!(matches!(err, ContractError::NotExpired {}));
+
+        env.block.height += 10;
+        let err = clawback(deps.as_mut(), &env, OWNER, 2).unwrap_err();
+        assert!(matches!(err, ContractError::NotExpired {}));
+        let err = clawback(deps.as_mut(), &env, ADMIN, 1).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // After it the owner reclaims the whole unpaid amount
+        let res = clawback(deps.as_mut(), &env, OWNER, 1).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: OWNER.to_string(),
+                amount: coins(10, "ujuno"),
+            })
+        );
+        let err = clawback(deps.as_mut(), &env, OWNER, 1).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotActive {}));
+
+        // Only the payment without expiry is left to pay
+        let msg = ExecuteMsg::Pay { limit: None };
+        let res = execute(deps.as_mut(), env, mock_info(PAYEE3, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.attributes[1], attr("paid_id", "2"));
+    }
+
+    #[test]
+    fn batch_stop_refunds() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let native = Payment {
+            on_receive: Some(to_binary(&ReceiverMsg::Stake {}).unwrap()),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1))
+        };
+        let token = Payment {
+            denom: String::new(),
+            token_address: Some(Addr::unchecked("token0001")),
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(5),
+                installments: 3,
+            }),
+            ..native.clone()
+        };
+        let msg = instantiate_msg(vec![native, token]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Refunds go straight to the owner without the recipient's callback
+        let mut refunds = vec![];
+        for id in 1..=2 {
+            let msg = ExecuteMsg::StopPayment { id, refund: None };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+            refunds.push(res.messages[0].msg.clone());
+        }
+        assert_eq!(
+            refunds,
+            vec![
+                CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                    to_address: OWNER.to_string(),
+                    amount: coins(10, "ujuno"),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: "token0001".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: OWNER.to_string(),
+                        amount: Uint128::new(30),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn paid_at() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let start = env.block.height + 1;
+
+        let payment = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(10),
+                installments: 2,
+            }),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(start))
+        };
+        let timed = Payment {
+            time: Expiration::AtTime(env.block.time),
+            recurrence: None,
+            ..payment.clone()
+        };
+        let msg = instantiate_msg(vec![payment, timed]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let mut pay = |env: &Env| -> Vec<Option<Expiration>> {
+            let msg = ExecuteMsg::Pay { limit: None };
+            execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+            let res = query(deps.as_ref(), env.clone(), QueryMsg::GetPayments {}).unwrap();
+            let res: PaymentsResponse = from_binary(&res).unwrap();
+            res.payments.into_iter().map(|p| p.paid_at).collect()
+        };
+
+        // Time based payments are stamped with the block time
+        env.block.height = start;
+        let paid_time = Some(Expiration::AtTime(env.block.time));
+        assert_eq!(pay(&env), vec![None, paid_time]);
+
+        // Only the final installment stamps the payment
+        env.block.height = start + 10;
+        assert_eq!(
+            pay(&env),
+            vec![Some(Expiration::AtHeight(start + 10)), paid_time]
+        );
+    }
+
+    #[test]
+    fn simulate_pay() {
+        let mut deps = mock_dependencies(&coins(15, "ujuno"));
+        let env = mock_env();
+
+        let payment =
+            |amount: u128| native_payment(PAYEE2, amount, Expiration::AtHeight(env.block.height));
+        let msg = instantiate_msg(vec![payment(10), payment(10), payment(5)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::SimulatePay {}).unwrap();
+        let simulated: SimulatePayResponse = from_binary(&res).unwrap();
+        let ids: Vec<u64> = simulated.payouts.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+
+        // Pay sends exactly the simulated payouts
+        let msg = ExecuteMsg::Pay { limit: None };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+        let sent: Vec<CosmosMsg> = res.messages.into_iter().map(|m| m.msg).collect();
+        let expected: Vec<CosmosMsg> = simulated
+            .payouts
+            .iter()
+            .map(|p| {
+                CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                    to_address: p.recipient.to_string(),
+                    amount: coins(p.amount.u128(), &p.denom),
+                })
+            })
+            .collect();
+        assert_eq!(sent, expected);
+
+        // Simulating does not change state, paying does
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::SimulatePay {}).unwrap();
+        let simulated: SimulatePayResponse = from_binary(&res).unwrap();
+        assert_eq!(simulated.payouts.len(), 1);
+        assert_eq!(simulated.payouts[0].id, 2);
+
+        // Payout addresses, fees and splits are simulated as Pay sends them
+        let mut deps = mock_dependencies(&coins(20, "ujuno"));
+        let split = Payment {
+            recipients: Some(vec![
+                (Addr::unchecked(PAYEE2), 5000),
+                (Addr::unchecked(PAYEE3), 5000),
+            ]),
+            ..payment(10)
+        };
+        let msg = instantiate_msg(vec![payment(10), split]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        CONFIG
+            .update(deps.as_mut().storage, |mut config| -> StdResult<_> {
+                config.fee_bps = 1000;
+                config.fee_collector = Some(Addr::unchecked(FUNDER));
+                Ok(config)
+            })
+            .unwrap();
+        let msg = ExecuteMsg::SetPayoutAddress {
+            address: Some(Addr::unchecked("rotated")),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(PAYEE2, &[]), msg).unwrap();
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::SimulatePay {}).unwrap();
+        let simulated: SimulatePayResponse = from_binary(&res).unwrap();
+        let payouts: Vec<(u64, &str, u128)> = simulated
+            .payouts
+            .iter()
+            .map(|p| (p.id, p.recipient.as_str(), p.amount.u128()))
+            .collect();
+        assert_eq!(
+            payouts,
+            vec![
+                (1, "rotated", 9),
+                (1, FUNDER, 1),
+                (2, "rotated", 4),
+                (2, PAYEE3, 5),
+                (2, FUNDER, 1),
+            ]
+        );
+
+        // The fee of a single payout is sent once its reply succeeds
+        let msg = ExecuteMsg::Pay { limit: None };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+        let delivered = Reply {
+            id: 1,
+            result: ContractResult::Ok(cosmwasm_std::SubMsgExecutionResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        let replied = reply(deps.as_mut(), env, delivered).unwrap();
+        let mut sent: Vec<String> = res
+            .messages
+            .into_iter()
+            .chain(replied.messages)
+            .map(|m| format!("{:?}", m.msg))
+            .collect();
+        let mut expected: Vec<String> = simulated
+            .payouts
+            .iter()
+            .map(|p| {
+                format!(
+                    "{:?}",
+                    CosmosMsg::<Empty>::Bank(cosmwasm_std::BankMsg::Send {
+                        to_address: p.recipient.to_string(),
+                        amount: coins(p.amount.u128(), &p.denom),
+                    })
+                )
+            })
+            .collect();
+        sent.sort();
+        expected.sort();
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn dedupe_payments() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = |height: u64| native_payment(PAYEE2, 10, Expiration::AtHeight(height));
+        let height = env.block.height;
+        let msg = instantiate_msg(vec![payment(height), payment(height + 1)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // The first payment is paid so adding it again is not a duplicate
+        let msg = ExecuteMsg::Pay { limit: None };
+        execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+
+        let add = |dedupe| ExecuteMsg::AddPayments {
+            schedule: vec![payment(height), payment(height + 1), payment(height + 2)],
+            dedupe,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            add(Some(true)),
+        )
+        .unwrap();
+        let data: AddPaymentsResponse = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(
+            data,
+            AddPaymentsResponse {
+                ids: vec![3, 4],
+                skipped: 1,
+            }
+        );
+
+        // Without dedupe everything is added
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), add(None)).unwrap();
+        let data: AddPaymentsResponse = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(data.ids, vec![5, 6, 7]);
+        assert_eq!(data.skipped, 0);
+    }
+
+    #[test]
+    fn milestone_dust() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let height = env.block.height;
+
+        // 7 split in thirds truncates on every milestone but the last
+        let milestones: Vec<Milestone> = [33, 33, 34]
+            .iter()
+            .enumerate()
+            .map(|(i, percent)| Milestone {
+                time: Expiration::AtHeight(height + i as u64),
+                percent: *percent,
+            })
+            .collect();
+        let msg = instantiate_msg(vec![Payment {
+            milestones: Some(milestones),
+            ..native_payment(PAYEE2, 7, Expiration::AtHeight(height))
+        }]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let mut paid = vec![];
+        for _ in 0..3 {
+            let msg = ExecuteMsg::Pay { limit: None };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+            for m in res.messages {
+                if let CosmosMsg::Bank(cosmwasm_std::BankMsg::Send { amount, .. }) = m.msg {
+                    paid.push(amount[0].amount.u128());
+                }
+            }
+            env.block.height += 1;
+        }
+
+        // The final milestone releases the dust so the full amount is paid
+        assert_eq!(paid, vec![2, 2, 3]);
+        assert_eq!(paid.iter().sum::<u128>(), 7);
+        let state = payments().load(deps.as_ref().storage, 1u64.into()).unwrap();
+        assert!(state.paid);
+        assert_eq!(remaining_payment(&state).unwrap().amount, Uint128::zero());
+    }
+
+    #[test]
+    fn ibc_payment() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = Payment {
+            ibc_channel: Some("channel-0".to_string()),
+            ..native_payment("osmo1remote", 10, Expiration::AtHeight(env.block.height))
+        };
+        let msg = instantiate_msg(vec![payment.clone()]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let update = ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: Some(Update::Set(60)),
+            fee: None,
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update).unwrap();
+
+        let msg = ExecuteMsg::Pay { limit: None };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Ibc(IbcMsg::Transfer {
+                channel_id: "channel-0".to_string(),
+                to_address: "osmo1remote".to_string(),
+                amount: coin(10, "ujuno"),
+                timeout: env.block.time.plus_seconds(60).into(),
+            })
+        );
+
+        // IBC transfers carry a single coin and no callback
+        let msg = ExecuteMsg::AddPayments {
+            schedule: vec![Payment {
+                extra_coins: Some(vec![coin(1, "uatom")]),
+                ..payment
+            }],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidIbcPayment {}));
+    }
+
+    #[test]
+    fn pause_payment() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let msg = instantiate_msg(vec![native_payment(
+            PAYEE2,
+            10,
+            Expiration::AtHeight(env.block.height),
+        )]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let mut run = |sender: &str, msg: ExecuteMsg| {
+            execute(deps.as_mut(), env.clone(), mock_info(sender, &[]), msg)
+        };
+
+        let err = run(PAYEE2, ExecuteMsg::PausePayment { id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let err = run(OWNER, ExecuteMsg::ResumePayment { id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::NotPaused {}));
+
+        run(OWNER, ExecuteMsg::PausePayment { id: 1 }).unwrap();
+        let err = run(OWNER, ExecuteMsg::PausePayment { id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::AlreadyPaused {}));
+
+        // Paused payments are not swept
+        let res = run(PAYEE3, ExecuteMsg::Pay { limit: None }).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        // Resuming pays what unlocked in the meantime
+        run(OWNER, ExecuteMsg::ResumePayment { id: 1 }).unwrap();
+        let res = run(PAYEE3, ExecuteMsg::Pay { limit: None }).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let err = run(OWNER, ExecuteMsg::PausePayment { id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotActive {}));
+    }
+
+    #[test]
+    fn payout_fee() {
+        let mut app = mock_app();
+        let (owner, funder, payee2, payee3) = get_accounts();
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+
+        let payment = native_payment(payee2.clone(), 1000, Expiration::AtHeight(1));
+        let token_payment = Payment {
+            denom: String::new(),
+            token_address: Some(cw20_addr.clone()),
+            ..payment.clone()
+        };
+        let vest_addr = instantiate_vest(&mut app, vec![payment, token_payment]);
+        app.init_bank_balance(&vest_addr, vec![coin(1000, "ujuno")])
+            .unwrap();
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr,
+            funder,
+            Uint128::new(1000),
+        );
+
+        let update = |bps: u16| ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: Some(Update::Set(Fee {
+                bps,
+                collector: Addr::unchecked(FUNDER),
+            })),
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        let err: ContractError = app
+            .execute_contract(owner.clone(), vest_addr.clone(), &update(10001), &[])
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(err, ContractError::InvalidFee {}));
+
+        // 2.5% of every payout goes to the collector
+        app.execute_contract(owner, vest_addr.clone(), &update(250), &[])
+            .unwrap();
+        let collector_cw20 = cw20.balance(&app, Addr::unchecked(FUNDER)).unwrap();
+        app.execute_contract(payee3, vest_addr, &ExecuteMsg::Pay { limit: None }, &[])
+            .unwrap();
+
+        let native = |addr: &Addr| {
+            app.wrap()
+                .query_balance(addr.clone(), "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(native(&payee2), 975);
+        assert_eq!(native(&Addr::unchecked(FUNDER)), 25);
+        assert_eq!(
+            cw20.balance(&app, payee2).unwrap().u128(),
+            INITIAL_BALANCE + 975
+        );
+        assert_eq!(
+            cw20.balance(&app, Addr::unchecked(FUNDER)).unwrap(),
+            collector_cw20 + Uint128::new(25)
+        );
+    }
+
+    #[test]
+    fn payout_fee_on_failed_payout() {
+        let mut app = mock_app();
+        let (owner, _funder, payee2, payee3) = get_accounts();
+        let collector = Addr::unchecked("collector");
+
+        let payment = native_payment(payee2.clone(), 100, Expiration::AtHeight(1));
+        let payments = vec![
+            // payee3 is not a contract, so the callback can't be delivered
+            Payment {
+                recipient: payee3.clone(),
+                on_receive: Some(to_binary(&ReceiverMsg::Stake {}).unwrap()),
+                ..payment.clone()
+            },
+            payment,
+        ];
+        let vest_addr = instantiate_vest(&mut app, payments);
+        app.init_bank_balance(&vest_addr, coins(200, "ujuno"))
+            .unwrap();
+        let update = ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: Some(Update::Set(Fee {
+                bps: 1000,
+                collector: collector.clone(),
+            })),
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        app.execute_contract(owner.clone(), vest_addr.clone(), &update, &[])
+            .unwrap();
+        app.execute_contract(
+            owner,
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+
+        let native = |addr: &Addr| {
+            app.wrap()
+                .query_balance(addr.clone(), "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        // Only the delivered payout pays its fee, the failed one keeps its funds
+        assert_eq!(native(&payee2), 90);
+        assert_eq!(native(&collector), 10);
+        assert_eq!(native(&payee3), 0);
+        assert_eq!(native(&vest_addr), 100);
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let paid: Vec<bool> = res.payments.iter().map(|p| p.paid).collect();
+        assert_eq!(paid, vec![false, true]);
+    }
+
+    #[test]
+    fn recipient_summary() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = |amount: u128, height: u64| {
+            native_payment(PAYEE2, amount, Expiration::AtHeight(height))
+        };
+        let height = env.block.height;
+        let msg = instantiate_msg(vec![
+            payment(1, height),
+            payment(2, height + 1),
+            payment(4, height + 1),
+            Payment {
+                recipient: Addr::unchecked(PAYEE3),
+                ..payment(8, height)
+            },
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Pay { limit: None };
+        execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+        let msg = ExecuteMsg::StopPayment {
+            id: 3,
+            refund: Some(false),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = QueryMsg::RecipientSummary {
+            recipient: Addr::unchecked(PAYEE2),
+        };
+        let summary: RecipientSummaryResponse =
+            from_binary(&query(deps.as_ref(), env, msg).unwrap()).unwrap();
+        let native = |amount: u128| Totals {
+            native: coins(amount, "ujuno"),
+            cw20: vec![],
+        };
+        assert_eq!(
+            summary,
+            RecipientSummaryResponse {
+                total_paid: native(1),
+                total_pending: native(2),
+                total_stopped: native(4),
+            }
+        );
+    }
+
+    #[test]
+    fn denom_with_token() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let msg = instantiate_msg(vec![Payment {
+            token_address: Some(Addr::unchecked("token0001")),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height))
+        }]);
+        let err = instantiate(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::DenomWithToken {}));
+    }
+
+    #[test]
+    fn pay_cursor() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let height = env.block.height;
+
+        let payment = |height: u64| native_payment(PAYEE2, 1, Expiration::AtHeight(height));
+        // Payment 2 unlocks later than the rest
+        let schedule = (1..=10)
+            .map(|id| payment(if id == 2 { height + 5 } else { height }))
+            .collect();
+        let msg = instantiate_msg(schedule);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let mut pay = |env: &Env| -> (Vec<u64>, u64) {
+            let msg = ExecuteMsg::Pay { limit: Some(4) };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+            let ids = res
+                .attributes
+                .into_iter()
+                .filter(|a| a.key == "paid_id")
+                .map(|a| a.value.parse().unwrap())
+                .collect();
+            (ids, LAST_SWEPT_ID.load(deps.as_ref().storage).unwrap())
+        };
+
+        assert_eq!(pay(&env), (vec![1, 3, 4, 5], 5));
+        assert_eq!(pay(&env), (vec![6, 7, 8, 9], 9));
+
+        // The sweep wraps around to pick up payment 2 and resets the cursor
+        env.block.height = height + 5;
+        assert_eq!(pay(&env), (vec![10, 2], 0));
+    }
+
+    #[test]
+    fn expected_cw20_total() {
+        let mut app = mock_app();
+        let cw20_addr = instantiate_cw20(&mut app);
+        let vest_id = app.store_code(contract_vest());
+
+        let info: TokenInfoResponse = app
+            .wrap()
+            .query_wasm_smart(&cw20_addr, &Cw20QueryMsg::TokenInfo {})
+            .unwrap();
+        let msg = |expected: Uint128| InstantiateMsg {
+            expected_cw20_total: Some(expected),
+            ..instantiate_msg(vec![cw20_payment(
+                PAYEE2,
+                10,
+                &cw20_addr,
+                Expiration::AtHeight(1),
+            )])
+        };
+
+        let too_much = info.total_supply + Uint128::new(1);
+        let err: ContractError = app
+            .instantiate_contract(
+                vest_id,
+                Addr::unchecked(OWNER),
+                &msg(too_much),
+                &[],
+                "vest",
+                None,
+            )
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(
+            err,
+            ContractError::Cw20SupplyTooLow { supply, expected, .. }
+                if supply == info.total_supply && expected == too_much
+        ));
+
+        app.instantiate_contract(
+            vest_id,
+            Addr::unchecked(OWNER),
+            &msg(info.total_supply),
+            &[],
+            "vest",
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        const ADMIN: &str = "admin0001";
+
+        let msg = InstantiateMsg {
+            admin: Addr::unchecked(ADMIN),
+            enforce_order: true,
+            ..instantiate_msg(vec![])
+        };
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let update = ExecuteMsg::UpdateConfig {
+            payout_start: Some(Update::Set(Expiration::AtHeight(100))),
+            schedule_locked: Some(true),
+            payers: Some(Update::Set(vec![Addr::unchecked(PAYEE3)])),
+            ibc_timeout_seconds: Some(Update::Set(120)),
+            fee: Some(Update::Set(Fee {
+                bps: 50,
+                collector: Addr::unchecked(FUNDER),
+            })),
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), update).unwrap();
+
+        let res = query(deps.as_ref(), env, QueryMsg::GetConfig {}).unwrap();
+        let config: ConfigResponse = from_binary(&res).unwrap();
+        assert_eq!(
+            config,
+            ConfigResponse {
+                owner: Addr::unchecked(OWNER),
+                admins: vec![Addr::unchecked(ADMIN)],
+                payout_start: Some(Expiration::AtHeight(100)),
+                schedule_locked: true,
+                payers: Some(vec![Addr::unchecked(PAYEE3)]),
+                enforce_order: true,
+                ibc_timeout_seconds: 120,
+                fee_bps: 50,
+                fee_collector: Some(Addr::unchecked(FUNDER)),
+                min_payout: None,
+                default_denom: None,
+                terminated: false,
+                stop_grace: None,
+                allowed_denoms: None,
+                allow_never: false,
+                payout_order: PayoutOrder::OldestFirst,
+                version: 1,
+                keeper_reward: None,
+                enabled: true,
+            }
+        );
+    }
+
+    #[test]
+    fn update_config_partial() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let update = |fee: Option<Update<Fee>>, min_payout: Option<Update<Uint128>>| {
+            ExecuteMsg::UpdateConfig {
+                payout_start: None,
+                schedule_locked: None,
+                payers: None,
+                ibc_timeout_seconds: None,
+                fee,
+                min_payout,
+                stop_grace: None,
+                payout_order: None,
+                expected_version: None,
+                keeper_reward: None,
+                owner: None,
+                enabled: None,
+            }
+        };
+        let config = |deps: &cosmwasm_std::OwnedDeps<_, _, _>| -> ConfigResponse {
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::GetConfig {}).unwrap())
+                .unwrap()
+        };
+        let fee = Fee {
+            bps: 50,
+            collector: Addr::unchecked(FUNDER),
+        };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            update(Some(Update::Set(fee)), None),
+        )
+        .unwrap();
+        let before = config(&deps);
+
+        // Only min_payout changes
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            update(None, Some(Update::Set(Uint128::new(5)))),
+        )
+        .unwrap();
+        assert_eq!(
+            config(&deps),
+            ConfigResponse {
+                min_payout: Some(Uint128::new(5)),
+                version: before.version + 1,
+                ..before.clone()
+            }
+        );
+        assert_eq!(config(&deps).owner, Addr::unchecked(OWNER));
+
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            update(Some(Update::Clear {}), None),
+        )
+        .unwrap();
+        let after = config(&deps);
+        assert_eq!((after.fee_bps, after.fee_collector), (0, None));
+        assert_eq!(after.min_payout, Some(Uint128::new(5)));
+    }
+
+    #[test]
+    fn update_config_owner_and_enabled() {
+        const ADMIN: &str = "admin0001";
+        let mut deps = mock_dependencies(&coins(10, "ujuno"));
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height));
+        let msg = InstantiateMsg {
+            admin: Addr::unchecked(ADMIN),
+            ..instantiate_msg(vec![payment])
+        };
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let update = |owner: Option<&str>, enabled: Option<bool>| ExecuteMsg::UpdateConfig {
+            owner: owner.map(Addr::unchecked),
+            enabled,
+            payout_start: None,
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: None,
+            min_payout: None,
+            stop_grace: None,
+            payout_order: None,
+            keeper_reward: None,
+            expected_version: None,
+        };
+        let config = |deps: &cosmwasm_std::OwnedDeps<_, _, _>| -> ConfigResponse {
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::GetConfig {}).unwrap())
+                .unwrap()
+        };
+
+        // Only enabled changes, and payouts are refused until it is set again
+        let before = config(&deps);
+        let msg = update(None, Some(false));
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), msg).unwrap();
+        assert_eq!(
+            config(&deps),
+            ConfigResponse {
+                enabled: false,
+                version: before.version + 1,
+                ..before
+            }
+        );
+        let pay = ExecuteMsg::Pay { limit: None };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            pay.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::PayoutsDisabled {}));
+        let msg = update(None, Some(true));
+        execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), msg).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), pay).unwrap();
+
+        // Only the owner can hand over ownership, and not to the contract
+        // itself. The owner is no admin, so it can't change anything else
+        let msg = update(Some(PAYEE3), None);
+        let err = execute(deps.as_mut(), env.clone(), mock_info(ADMIN, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let msg = update(Some(PAYEE3), Some(false));
+        let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let msg = update(Some(env.contract.address.as_str()), None);
+        let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidOwner {}));
+        let msg = update(Some(PAYEE3), None);
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(config(&deps).owner, Addr::unchecked(PAYEE3));
+        assert_eq!(config(&deps).admins, vec![Addr::unchecked(ADMIN)]);
+    }
+
+    #[test]
+    fn update_config_version() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let update = |expected_version: u64| ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: None,
+            min_payout: Some(Update::Set(Uint128::new(5))),
+            stop_grace: None,
+            payout_order: None,
+            expected_version: Some(expected_version),
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update(0)).unwrap();
+
+        // A second admin working from version 0 would clobber the first change
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update(0)).unwrap_err();
+        assert!(matches!(err, ContractError::StaleConfig {}));
+
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update(1)).unwrap();
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::GetConfig {}).unwrap()).unwrap();
+        assert_eq!(config.version, 2);
+    }
+
+    #[test]
+    fn invalid_owner() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        let contract = env.contract.address.clone();
+
+        for admin in [Addr::unchecked(""), contract.clone()] {
+            let msg = InstantiateMsg {
+                admin,
+                ..instantiate_msg(vec![])
+            };
+            let err =
+                instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidOwner {}));
+        }
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        for address in [Addr::unchecked(""), contract] {
+            let msg = ExecuteMsg::AddAdmin { address };
+            let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidOwner {}));
+        }
+    }
+
+    #[test]
+    fn payments_in_range() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = |time: Expiration| native_payment(PAYEE2, 1, time);
+        let mut schedule: Vec<Payment> = (1..=5)
+            .map(|i| payment(Expiration::AtHeight(100 * i)))
+            .collect();
+        schedule.push(payment(Expiration::AtTime(env.block.time)));
+        let msg = instantiate_msg(schedule);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = QueryMsg::PaymentsInRange {
+            from: Expiration::AtHeight(200),
+            to: Expiration::AtHeight(400),
+        };
+        let res: PaymentsResponse = from_binary(&query(deps.as_ref(), env, msg).unwrap()).unwrap();
+        let ids: Vec<u64> = res.payments.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn replace_schedule() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let height = env.block.height;
+
+        let payment = |amount: u128| native_payment(PAYEE2, amount, Expiration::AtHeight(height));
+        let recurring = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(10),
+                installments: 2,
+            }),
+            ..payment(3)
+        };
+        let token = Payment {
+            denom: String::new(),
+            token_address: Some(Addr::unchecked("token0001")),
+            time: Expiration::AtHeight(height + 10),
+            ..payment(5)
+        };
+        let msg = instantiate_msg(vec![payment(1), recurring, token]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Pays payment 1 and the first installment of payment 2
+        let msg = ExecuteMsg::Pay { limit: Some(2) };
+        execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+
+        env.block.height += 5;
+        let msg = ExecuteMsg::ReplaceSchedule {
+            schedule: vec![payment(7), payment(8)],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        let data: AddPaymentsResponse = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(data.ids, vec![4, 5]);
+        assert_eq!(
+            res.attributes
+                .iter()
+                .filter(|a| a.key == "refund")
+                .collect::<Vec<_>>(),
+            vec![
+                &refund_attribute(2, "replace"),
+                &refund_attribute(3, "replace")
+            ]
+        );
+
+        // Unpaid amounts are refunded to the owner per denom and token
+        let refunds: Vec<CosmosMsg> = res.messages.into_iter().map(|m| m.msg).collect();
+        assert_eq!(
+            refunds,
+            vec![
+                CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                    to_address: OWNER.to_string(),
+                    amount: coins(3, "ujuno"),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: "token0001".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                        recipient: OWNER.to_string(),
+                        amount: Uint128::new(5),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+            ]
+        );
+
+        // The paid and partially paid records are kept
+        let res = query(deps.as_ref(), env, QueryMsg::GetPayments {}).unwrap();
+        let res: PaymentsResponse = from_binary(&res).unwrap();
+        let ids: Vec<(u64, bool, bool)> = res
+            .payments
+            .iter()
+            .map(|p| (p.id, p.paid, p.stopped))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                (1, true, false),
+                (2, false, true),
+                (4, false, false),
+                (5, false, false)
+            ]
+        );
+        // Counts ids issued, so payment 3 being removed doesn't lower it
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetPaymentsCount {}).unwrap();
+        let count: PaymentsCountResponse = from_binary(&res).unwrap();
+        assert_eq!(count.total, 5);
+    }
+
+    #[test]
+    fn replace_schedule_stop_grace() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+        let height = env.block.height;
+
+        let payment =
+            |amount: u128| native_payment(PAYEE2, amount, Expiration::AtHeight(height + 10));
+        let msg = instantiate_msg(vec![payment(1), payment(2)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        CONFIG
+            .update(deps.as_mut().storage, |mut config| -> StdResult<_> {
+                config.stop_grace = Some(Duration::Height(5));
+                Ok(config)
+            })
+            .unwrap();
+        let msg = ExecuteMsg::StopPayment {
+            id: 1,
+            refund: Some(true),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Nothing is refunded or removed before the grace period ends
+        env.block.height += 1;
+        let msg = ExecuteMsg::ReplaceSchedule {
+            schedule: vec![payment(3)],
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        assert!(res.messages.is_empty());
+        let stopping = |deps: &cosmwasm_std::OwnedDeps<_, _, _>| {
+            query_payments(deps.as_ref())
+                .payments
+                .iter()
+                .map(|p| p.stopping.as_ref().map(|s| s.effective_at))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            stopping(&deps),
+            vec![
+                Some(Expiration::AtHeight(height + 5)),
+                Some(Expiration::AtHeight(height + 6)),
+                None
+            ]
+        );
+
+        env.block.height += 5;
+        let msg = ExecuteMsg::FinalizeStop { id: 2 };
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: OWNER.to_string(),
+                amount: coins(2, "ujuno"),
+            })
+        );
+    }
+
+    #[test]
+    fn replace_and_revoke_after_active_from() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = Payment {
+            active_from: Some(Expiration::AtHeight(env.block.height)),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 10))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::ReplaceSchedule { schedule: vec![] };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentActive {}));
+        let msg = ExecuteMsg::Revoke { id: 1 };
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentActive {}));
+
+        let p = load_payment(deps.as_ref().storage, 1).unwrap();
+        assert!(!p.stopped && !p.paid);
+    }
+
+    #[test]
+    fn aggregation_overflow() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = native_payment(
+            PAYEE2,
+            u128::MAX - 1,
+            Expiration::AtHeight(env.block.height),
+        );
+        let msg = instantiate_msg(vec![payment.clone()]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        // Adding payments keeps running totals, which reject the second one, so
+        // it is written to storage directly
+        let mut second = payments().load(deps.as_ref().storage, 1u64.into()).unwrap();
+        second.id = 2;
+        payments()
+            .save(deps.as_mut().storage, 2u64.into(), &second)
+            .unwrap();
+
+        // Summing the two payments errors instead of panicking
+        let recipient = Addr::unchecked(PAYEE2);
+        let err = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Claimable {
+                recipient: recipient.clone(),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, StdError::Overflow { .. }));
+
+        let err = query(deps.as_ref(), env, QueryMsg::RecipientSummary { recipient }).unwrap_err();
+        assert!(matches!(err, StdError::Overflow { .. }));
+    }
+
+    #[test]
+    fn stop_grant() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let native = Payment {
+            grant_id: Some(7),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 10))
+        };
+        let token = Payment {
+            amount: Uint128::new(20),
+            denom: String::new(),
+            token_address: Some(Addr::unchecked("token0001")),
+            ..native.clone()
+        };
+        let other = Payment {
+            grant_id: None,
+            ..native.clone()
+        };
+        let msg = instantiate_msg(vec![native, token, other]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::StopGrant {
+            grant_id: 7,
+            refund: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE2, &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // Both halves are refunded together
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 2);
+        let data: Vec<StopPaymentResponse> = from_binary(&res.data.unwrap()).unwrap();
+        let refunds: Vec<(u64, Uint128)> = data.iter().map(|r| (r.id, r.refunded)).collect();
+        assert_eq!(refunds, vec![(1, Uint128::new(10)), (2, Uint128::new(20))]);
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::GetPayments {}).unwrap();
+        let res: PaymentsResponse = from_binary(&res).unwrap();
+        let stopped: Vec<bool> = res.payments.iter().map(|p| p.stopped).collect();
+        assert_eq!(stopped, vec![true, true, false]);
+
+        // Nothing left to stop in the grant
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::GrantNotFound { grant_id: 7 }));
+    }
+
+    #[test]
+    fn min_payout() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(1),
+                installments: 5,
+            }),
+            ..native_payment(PAYEE2, 1, Expiration::AtHeight(env.block.height))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let update = ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: None,
+            min_payout: Some(Update::Set(Uint128::new(3))),
+            stop_grace: None,
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update).unwrap();
+
+        let mut pay = |env: &Env| {
+            let msg = ExecuteMsg::Pay { limit: None };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+            res.messages
+                .into_iter()
+                .map(|m| m.msg)
+                .collect::<Vec<CosmosMsg>>()
+        };
+        let sent = |amount: u128| {
+            vec![CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: PAYEE2.to_string(),
+                amount: coins(amount, "ujuno"),
+            })]
+        };
+
+        // A single unit is below the threshold
+        assert_eq!(pay(&env), vec![]);
+        env.block.height += 1;
+        assert_eq!(pay(&env), vec![]);
+
+        // Fires once three units have accumulated
+        env.block.height += 1;
+        assert_eq!(pay(&env), sent(3));
+
+        // The final installment pays out even though it is below the threshold
+        env.block.height += 2;
+        assert_eq!(pay(&env), sent(2));
+    }
+
+    #[test]
+    fn is_admin() {
+        const ADMIN: &str = "admin0001";
+        let mut deps = mock_dependencies(&[]);
+        let msg = InstantiateMsg {
+            admin: Addr::unchecked(ADMIN),
+            ..instantiate_msg(vec![])
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let is_admin = |address: &str| -> bool {
+            let msg = QueryMsg::IsAdmin {
+                address: Addr::unchecked(address),
+            };
+            from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap()
+        };
+        // The owner is not in admins but still has owner only actions
+        assert!(is_admin(OWNER));
+        assert!(is_admin(ADMIN));
+        assert!(!is_admin("random0001"));
+    }
+
+    #[test]
+    fn denom_validation() {
+        let instantiate_with = |denom: &str| {
+            let mut deps = mock_dependencies(&[]);
+            let env = mock_env();
+            let payment = Payment {
+                denom: denom.to_string(),
+                ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1))
+            };
+            let msg = instantiate_msg(vec![payment]);
+            instantiate(deps.as_mut(), env, mock_info(OWNER, &[]), msg)
+        };
+
+        for denom in [
+            "ujuno",
+            "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2",
+            "factory/juno1abc/uToken",
+        ] {
+            instantiate_with(denom).unwrap();
+        }
+        // Mixed case plain denoms would be counted apart from their lowercase form
+        for denom in ["uJuno", "UJUNO", "1juno", "uj", "u juno", "ujuno!"] {
+            let err = instantiate_with(denom).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidDenom {}), "{}", denom);
+        }
+    }
+
+    #[test]
+    fn default_denom() {
+        let mut deps = mock_dependencies(&[coin(1000, "ujuno"), coin(1000, "uatom")]);
+        let env = mock_env();
+
+        let payment = |amount: u128, denom: &str| Payment {
+            denom: denom.to_string(),
+            ..native_payment(PAYEE2, amount, Expiration::AtHeight(env.block.height))
+        };
+        let schedule = vec![payment(1, ""), payment(2, ""), payment(3, "uatom")];
+
+        // Without a default every native payment needs its own denom
+        let msg = instantiate_msg(schedule.clone());
+        let err = instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::MissingDenom {}));
+
+        let mut deps = mock_dependencies(&[coin(1000, "ujuno"), coin(1000, "uatom")]);
+        let msg = InstantiateMsg {
+            default_denom: Some("ujuno".to_string()),
+            ..instantiate_msg(schedule)
+        };
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::AddPayments {
+            schedule: vec![payment(4, "")],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Pay { limit: None };
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap();
+        let sent: Vec<CosmosMsg> = res.messages.into_iter().map(|m| m.msg).collect();
+        let send = |amount: u128, denom: &str| {
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: PAYEE2.to_string(),
+                amount: coins(amount, denom),
+            })
+        };
+        assert_eq!(
+            sent,
+            vec![
+                send(1, "ujuno"),
+                send(2, "ujuno"),
+                send(3, "uatom"),
+                send(4, "ujuno")
+            ]
+        );
+    }
+
+    #[test]
+    fn fund_then_pay() {
+        let mut app = mock_app();
+
+        let (_owner, funder, payee2, _payee3) = get_accounts();
+
+        let current_height = app.block_info().height;
+
+        let denom = String::from("ujuno");
+        let payments = vec![Payment {
+            denom: denom.clone(),
+            ..native_payment(payee2.clone(), 5, Expiration::AtHeight(current_height))
+        }];
+        let vest_addr = instantiate_vest(&mut app, payments);
+        app.init_bank_balance(&funder, vec![coin(5, denom.clone())])
+            .unwrap();
+
+        // Funding alone leaves the payment waiting
+        let res = app
+            .execute_contract(
+                funder.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Fund { then_pay: false },
+                &[coin(2, denom.clone())],
+            )
+            .unwrap();
+        assert_eq!(
+            res.custom_attrs(1),
+            [attr("method", "fund"), attr("funded", "2ujuno")]
+        );
+        let balance = |app: &App<Empty>, addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, denom.clone())
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(balance(&app, &payee2), 0);
+
+        // The sweep sees the funds sent with the same message
+        app.execute_contract(
+            funder.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Fund { then_pay: true },
+            &[coin(3, denom.clone())],
+        )
+        .unwrap();
+        assert_eq!(balance(&app, &payee2), 5);
+        assert_eq!(balance(&app, &vest_addr), 0);
+
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        assert!(res.payments[0].paid);
+    }
+
+    #[test]
+    fn invalid_token_address() {
+        let mut deps = mock_dependencies(&coins(10, "ujuno"));
+        let mut env = mock_env();
+
+        let time = Expiration::AtHeight(env.block.height);
+        let msg = instantiate_msg(vec![native_payment(PAYEE3, 10, time)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Stored directly, as AddPayments would reject the address
+        let payment = cw20_payment(
+            PAYEE2,
+            1,
+            &Addr::unchecked("x"),
+            Expiration::AtHeight(env.block.height),
+        );
+        payments()
+            .save(
+                deps.as_mut().storage,
+                3u64.into(),
+                &PaymentState {
+                    payment,
+                    paid: false,
+                    id: 3,
+                    installments_paid: 0,
+                    stopped: false,
+                    paused: false,
+                    paid_at: None,
+                    stopping: None,
+                    fail_count: 0,
+                },
+            )
+            .unwrap();
+
+        // The other payment still goes out, the bad one counts as a failure
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        let err = ContractError::InvalidTokenAddress { id: 3 }.to_string();
+        assert!(res.attributes.contains(&attr("failed_id", "3")));
+        assert!(res.attributes.contains(&attr("error", err)));
+        assert!(load_payment(&deps.storage, 1).unwrap().paid);
+
+        for attempt in 2..=MAX_PAYOUT_FAILURES {
+            env.block.height += 1;
+            let res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(OWNER, &[]),
+                ExecuteMsg::Pay { limit: None },
+            )
+            .unwrap();
+            let quarantined = res.attributes.contains(&attr("quarantined_id", "3"));
+            assert_eq!(quarantined, attempt == MAX_PAYOUT_FAILURES);
+        }
+        let p = load_payment(&deps.storage, 3).unwrap();
+        assert_eq!(p.fail_count, MAX_PAYOUT_FAILURES);
+        assert!(p.paused);
+        assert!(!p.paid);
+    }
+
+    #[test]
+    fn empty_denom_payout() {
+        let mut deps = mock_dependencies(&coins(10, "ujuno"));
+        let env = mock_env();
+
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        // Stored directly, as AddPayments would reject the denom
+        let payment = Payment {
+            denom: String::new(),
+            ..native_payment(PAYEE2, 1, Expiration::AtHeight(env.block.height))
+        };
+        payments()
+            .save(
+                deps.as_mut().storage,
+                4u64.into(),
+                &PaymentState {
+                    payment,
+                    paid: false,
+                    id: 4,
+                    installments_paid: 0,
+                    stopped: false,
+                    paused: false,
+                    paid_at: None,
+                    stopping: None,
+                    fail_count: 0,
+                },
+            )
+            .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        let err = ContractError::InvalidDenom {}.to_string();
+        assert!(res.attributes.contains(&attr("failed_id", "4")));
+        assert!(res.attributes.contains(&attr("error", err)));
+        let p = load_payment(&deps.storage, 4).unwrap();
+        assert_eq!(p.fail_count, 1);
+        assert!(!p.paid);
+
+        // The recipient claiming fails the same way instead of reverting
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info(PAYEE2, &[]),
+            ExecuteMsg::ClaimAll {},
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        assert_eq!(load_payment(&deps.storage, 4).unwrap().fail_count, 2);
+    }
+
+    #[test]
+    fn weighted_recipients() {
+        let mut app = mock_app();
+
+        let (owner, funder, payee2, payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+
+        let payments = vec![Payment {
+            recipients: Some(vec![(payee2.clone(), 6000), (payee3.clone(), 4000)]),
+            ..cw20_payment(owner, 100, &cw20_addr, Expiration::AtHeight(1))
+        }];
+        let vest_addr = instantiate_vest(&mut app, payments);
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr,
+            funder,
+            Uint128::new(100),
+        );
+
+        let balance =
+            |app: &App<Empty>, addr: &Addr| cw20.balance(app, addr.clone()).unwrap().u128();
+        let initial2 = balance(&app, &payee2);
+        let initial3 = balance(&app, &payee3);
+
+        app.execute_contract(
+            payee3.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(balance(&app, &payee2), initial2 + 60);
+        assert_eq!(balance(&app, &payee3), initial3 + 40);
+        assert_eq!(balance(&app, &vest_addr), 0);
+    }
+
+    #[test]
+    fn weighted_recipients_zero_shares() {
+        let mut deps = mock_dependencies(&[coin(1, "ujuno"), coin(3, "uatom")]);
+        let env = mock_env();
+
+        let payment = Payment {
+            extra_coins: Some(vec![coin(3, "uatom")]),
+            recipients: Some(vec![
+                (Addr::unchecked(PAYEE2), 5000),
+                (Addr::unchecked(PAYEE3), 5000),
+            ]),
+            ..native_payment(OWNER, 1, Expiration::AtHeight(env.block.height))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        CONFIG
+            .update(deps.as_mut().storage, |mut config| -> StdResult<_> {
+                config.fee_bps = 5000;
+                config.fee_collector = Some(Addr::unchecked(FUNDER));
+                Ok(config)
+            })
+            .unwrap();
+
+        // The fee and the first share round down to nothing in ujuno, which
+        // is left out rather than sent as a zero coin
+        let msg = ExecuteMsg::Pay { limit: None };
+        let res = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap();
+        let send = |to: &str, amount: Vec<Coin>| {
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: to.to_string(),
+                amount,
+            })
+        };
+        assert_eq!(
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>(),
+            vec![
+                send(PAYEE2, coins(1, "uatom")),
+                send(PAYEE3, vec![coin(1, "ujuno"), coin(1, "uatom")]),
+                send(FUNDER, coins(1, "uatom")),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_recipient_weights() {
+        let mut deps = mock_dependencies(&[]);
+
+        let payment = Payment {
+            recipients: Some(vec![
+                (Addr::unchecked(PAYEE2), 6000),
+                (Addr::unchecked(PAYEE3), 3000),
+            ]),
+            ..native_payment(PAYEE2, 100, Expiration::AtHeight(1))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidSplit {}));
+    }
+
+    #[test]
+    fn undelivered_cw20_payout() {
+        let mut app = mock_app();
+
+        let (owner, funder, _payee2, payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+        let noop_id = app.store_code(contract_noop_cw20());
+        let noop_addr = app
+            .instantiate_contract(noop_id, owner.clone(), &Empty {}, &[], "noop", None)
+            .unwrap();
+
+        let payment = |token_address: Addr| {
+            cw20_payment(owner.clone(), 1, &token_address, Expiration::AtHeight(1))
+        };
+        let payments = vec![payment(noop_addr), payment(cw20_addr.clone())];
         let vest_addr = instantiate_vest(&mut app, payments);
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr,
+            funder,
+            Uint128::new(1),
+        );
+        let initial_balance = cw20.balance(&app, owner.clone()).unwrap().u128();
+
+        let res = app
+            .execute_contract(
+                payee3,
+                vest_addr.clone(),
+                &ExecuteMsg::Pay { limit: None },
+                &[],
+            )
+            .unwrap();
+        assert!(res.events.iter().any(|e| e
+            .attributes
+            .contains(&attr("error", "cw20 transfer was not received"))));
+        assert_eq!(
+            cw20.balance(&app, owner).unwrap().u128(),
+            initial_balance + 1
+        );
+
+        // The transfer that did nothing leaves its payment unpaid
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let paid: Vec<bool> = res.payments.iter().map(|p| p.paid).collect();
+        assert_eq!(paid, vec![false, true]);
+
+        let res: PaymentsCountResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPaymentsCount {})
+            .unwrap();
+        assert_eq!(res.paid, 1);
+    }
+
+    #[test]
+    fn max_schedule_len() {
+        let payment = native_payment(PAYEE2, 1, Expiration::AtHeight(1));
+        let msg = |len: usize| instantiate_msg(vec![payment.clone(); len]);
+
+        let mut deps = mock_dependencies(&[]);
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            msg(MAX_SCHEDULE_LEN + 1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::TooManyPayments { .. }));
+
+        let mut deps = mock_dependencies(&[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            msg(MAX_SCHEDULE_LEN),
+        )
+        .unwrap();
+
+        // The limit is per call, not on the stored total
+        let add = |len: usize| ExecuteMsg::AddPayments {
+            schedule: vec![payment.clone(); len],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            add(MAX_SCHEDULE_LEN + 1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::TooManyPayments { .. }));
+        execute(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), add(1)).unwrap();
+    }
+
+    #[test]
+    fn get_balances() {
+        let mut app = mock_app();
+
+        let (owner, funder, _payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let current_height = app.block_info().height;
+
+        let payment = native_payment(owner, 10, Expiration::AtHeight(current_height + 1));
+        let token_payment = Payment {
+            amount: Uint128::new(20),
+            denom: String::new(),
+            token_address: Some(cw20_addr.clone()),
+            ..payment.clone()
+        };
+        let vest_addr = instantiate_vest(&mut app, vec![payment, token_payment]);
+
+        app.init_bank_balance(&vest_addr, vec![coin(4, "ujuno")])
+            .unwrap();
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr.clone(),
+            funder,
+            Uint128::new(15),
+        );
+
+        let res: BalancesResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetBalances {})
+            .unwrap();
+        assert_eq!(
+            res.balances,
+            Totals {
+                native: coins(4, "ujuno"),
+                cw20: vec![Cw20CoinVerified {
+                    address: cw20_addr.clone(),
+                    amount: Uint128::new(15),
+                }],
+            }
+        );
+        assert_eq!(
+            res.outstanding,
+            Totals {
+                native: coins(10, "ujuno"),
+                cw20: vec![Cw20CoinVerified {
+                    address: cw20_addr,
+                    amount: Uint128::new(20),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn relative_times() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = |time_offset: Duration| Payment {
+            time_offset: Some(time_offset),
+            ..native_payment(PAYEE2, 1, Expiration::Never {})
+        };
+        let msg = instantiate_msg(vec![
+            payment(Duration::Height(10)),
+            payment(Duration::Height(20)),
+            payment(Duration::Time(60)),
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::GetPayments {}).unwrap();
+        let res: PaymentsResponse = from_binary(&res).unwrap();
+        let times: Vec<(Expiration, Option<Duration>)> = res
+            .payments
+            .into_iter()
+            .map(|p| (p.payment.time, p.payment.time_offset))
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                (Expiration::AtHeight(env.block.height + 10), None),
+                (Expiration::AtHeight(env.block.height + 20), None),
+                (Expiration::AtTime(env.block.time.plus_seconds(60)), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_batch() {
+        let mut deps = mock_dependencies(&[]);
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let payment = native_payment(PAYEE2, 1, Expiration::AtHeight(1));
+        let add = ExecuteMsg::AddPayments {
+            schedule: vec![payment.clone(), payment],
+            dedupe: None,
+            batch_id: Some("batch-1".to_string()),
+            auto_pay: None,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            add.clone(),
+        )
+        .unwrap();
+
+        // A retry of the same batch adds nothing
+        let err = execute(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), add).unwrap_err();
+        assert!(matches!(err, ContractError::DuplicateBatch {}));
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetPayments {}).unwrap();
+        let res: PaymentsResponse = from_binary(&res).unwrap();
+        assert_eq!(res.payments.len(), 2);
+    }
+
+    #[test]
+    fn terminate() {
+        let mut app = mock_app();
+
+        let (owner, funder, payee2, _payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+        let current_height = app.block_info().height;
+
+        let payment = native_payment(payee2.clone(), 3, Expiration::AtHeight(current_height + 10));
+        let token_payment = Payment {
+            amount: Uint128::new(5),
+            denom: String::new(),
+            token_address: Some(cw20_addr.clone()),
+            ..payment.clone()
+        };
+        let vest_addr = instantiate_vest(&mut app, vec![payment.clone(), token_payment]);
+
+        // Funded beyond what the payments need
+        app.init_bank_balance(&vest_addr, vec![coin(10, "ujuno")])
+            .unwrap();
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr,
+            funder,
+            Uint128::new(8),
+        );
+        let initial_cw20 = cw20.balance(&app, owner.clone()).unwrap().u128();
+
+        let err: ContractError = app
+            .execute_contract(
+                payee2.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Terminate {},
+                &[],
+            )
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        app.execute_contract(
+            owner.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Terminate {},
+            &[],
+        )
+        .unwrap();
+        let native = |addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(native(&owner), 10);
+        assert_eq!(native(&vest_addr), 0);
+        assert_eq!(
+            cw20.balance(&app, owner.clone()).unwrap().u128(),
+            initial_cw20 + 8
+        );
+        assert_eq!(cw20.balance(&app, vest_addr.clone()).unwrap().u128(), 0);
+
+        let res: PaymentsCountResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPaymentsCount {})
+            .unwrap();
+        assert_eq!(res.stopped, 2);
+
+        // Nothing can be paid or added afterwards
+        app.update_block(|b| b.height += 10);
+        let err: ContractError = app
+            .execute_contract(
+                owner.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Pay { limit: None },
+                &[],
+            )
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(err, ContractError::Terminated {}));
+        let add = ExecuteMsg::AddPayments {
+            schedule: vec![payment],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err: ContractError = app
+            .execute_contract(owner, vest_addr, &add, &[])
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(err, ContractError::Terminated {}));
+    }
+
+    #[test]
+    fn terminate_underfunded() {
+        let mut app = mock_app();
+
+        let (owner, _funder, payee2, _payee3) = get_accounts();
+        let current_height = app.block_info().height;
+
+        let payment = native_payment(payee2.clone(), 10, Expiration::AtHeight(current_height + 1));
+        let vest_addr = instantiate_vest(&mut app, vec![payment]);
+        app.init_bank_balance(&vest_addr, vec![coin(4, "ujuno")])
+            .unwrap();
+
+        // Matured, but Pay holds it back as the balance can't cover it
+        app.update_block(next_block);
+        let res = app
+            .execute_contract(
+                owner.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Pay { limit: None },
+                &[],
+            )
+            .unwrap();
+        assert!(!res
+            .events
+            .iter()
+            .any(|e| e.attributes.iter().any(|a| a.key == "paid_id")));
+
+        let res = app
+            .execute_contract(
+                owner.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::Terminate {},
+                &[],
+            )
+            .unwrap();
+        let wasm = res.events.iter().find(|e| e.ty == "wasm").unwrap();
+        assert!(wasm.attributes.contains(&refund_attribute(1, "terminate")));
+        let native = |addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(native(&owner), 4);
+        assert_eq!(native(&payee2), 0);
+        assert_eq!(native(&vest_addr), 0);
+
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        assert!(res.payments[0].stopped);
+        assert!(!res.payments[0].paid);
+    }
+
+    #[test]
+    fn payments_grouped() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = |height: u64| native_payment(PAYEE2, 1, Expiration::AtHeight(height));
+        let height = env.block.height;
+        let msg = instantiate_msg(vec![
+            payment(height),
+            payment(height + 10),
+            payment(height + 10),
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let pay = ExecuteMsg::Pay { limit: None };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), pay).unwrap();
+        let stop = ExecuteMsg::StopPayment {
+            id: 3,
+            refund: Some(false),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), stop).unwrap();
+
+        let res = query(deps.as_ref(), env, QueryMsg::GetPaymentsGrouped {}).unwrap();
+        let res: GroupedPaymentsResponse = from_binary(&res).unwrap();
+        let ids = |payments: &[PaymentState]| payments.iter().map(|p| p.id).collect::<Vec<u64>>();
+        assert_eq!(ids(&res.paid), vec![1]);
+        assert_eq!(ids(&res.active), vec![2]);
+        assert_eq!(ids(&res.stopped), vec![3]);
+    }
+
+    #[test]
+    fn stop_grace() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(10),
+                installments: 4,
+            }),
+            ..native_payment(PAYEE2, 1, Expiration::AtHeight(env.block.height))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        let update = ExecuteMsg::UpdateConfig {
+            payout_start: None,
+            schedule_locked: None,
+            payers: None,
+            ibc_timeout_seconds: None,
+            fee: None,
+            min_payout: None,
+            stop_grace: Some(Update::Set(Duration::Height(15))),
+            payout_order: None,
+            expected_version: None,
+            keeper_reward: None,
+            owner: None,
+            enabled: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update).unwrap();
+
+        let pay = ExecuteMsg::Pay { limit: None };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            pay.clone(),
+        )
+        .unwrap();
+
+        // Stopping only starts the grace period
+        let stop = ExecuteMsg::StopPayment {
+            id: 1,
+            refund: None,
+        };
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            stop.clone(),
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        let effective_at = Expiration::AtHeight(env.block.height + 15);
+        assert!(res
+            .attributes
+            .contains(&attr("effective_at", effective_at.to_string())));
+        let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), stop).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotActive {}));
+
+        let finalize = ExecuteMsg::FinalizeStop { id: 1 };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            finalize.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::GraceNotElapsed {}));
+
+        // Installments vesting within the grace period are still paid
+        env.block.height += 10;
+        let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), pay).unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        // Once it passes, the rest is refunded
+        env.block.height += 5;
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            finalize.clone(),
+        )
+        .unwrap();
+        let data: StopPaymentResponse = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(data.refunded, Uint128::new(2));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: OWNER.to_string(),
+                amount: coins(2, "ujuno"),
+            })
+        );
+
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), finalize).unwrap_err();
+        assert!(matches!(err, ContractError::NotStopping {}));
+    }
+
+    #[test]
+    fn allowed_denoms() {
+        let payment = |denom: &str, token_address: Option<Addr>| Payment {
+            denom: denom.to_string(),
+            token_address,
+            ..native_payment(PAYEE2, 1, Expiration::AtHeight(1))
+        };
+        let msg = |schedule: Vec<Payment>| InstantiateMsg {
+            allowed_denoms: Some(vec!["ujuno".to_string()]),
+            ..instantiate_msg(schedule)
+        };
+
+        let mut deps = mock_dependencies(&[]);
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            msg(vec![payment("uatom", None)]),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::DenomNotAllowed {}));
+
+        // cw20 payments are not affected
+        let mut deps = mock_dependencies(&[]);
+        let schedule = vec![
+            payment("ujuno", None),
+            payment("", Some(Addr::unchecked("token0001"))),
+        ];
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            msg(schedule),
+        )
+        .unwrap();
+
+        let add = ExecuteMsg::AddPayments {
+            schedule: vec![payment("uatom", None)],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), add).unwrap_err();
+        assert!(matches!(err, ContractError::DenomNotAllowed {}));
+    }
+
+    #[test]
+    fn progress() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height));
+        let recurring = Payment {
+            amount: Uint128::new(5),
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(10),
+                installments: 4,
+            }),
+            ..payment.clone()
+        };
+        let msg = instantiate_msg(vec![payment, recurring]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let progress = |deps: &cosmwasm_std::OwnedDeps<_, _, _>, env: &Env| -> ProgressResponse {
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Progress {}).unwrap()).unwrap()
+        };
+        assert_eq!(
+            progress(&deps, &env),
+            ProgressResponse {
+                total: Uint128::new(30),
+                released: Uint128::zero(),
+                percent_bps: 0,
+            }
+        );
+
+        let pay = ExecuteMsg::Pay { limit: None };
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            pay.clone(),
+        )
+        .unwrap();
+        assert_eq!(progress(&deps, &env).percent_bps, 5000);
+
+        env.block.height += 10;
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), pay).unwrap();
+        let res = progress(&deps, &env);
+        assert_eq!(res.released, Uint128::new(20));
+        assert_eq!(res.percent_bps, 6666);
+
+        // Stopping drops the unpaid remainder from the total
+        let stop = ExecuteMsg::StopPayment {
+            id: 2,
+            refund: Some(false),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), stop).unwrap();
+        assert_eq!(progress(&deps, &env).percent_bps, 10000);
+    }
+
+    #[test]
+    fn auto_pay_on_add() {
+        let mut app = mock_app();
+
+        let (owner, _funder, payee2, _payee3) = get_accounts();
+
+        let current_height = app.block_info().height;
+        let vest_addr = instantiate_vest(&mut app, vec![]);
+        app.init_bank_balance(&vest_addr, vec![coin(10, "ujuno")])
+            .unwrap();
+
+        let payment = |amount: u128, height: u64| {
+            native_payment(payee2.clone(), amount, Expiration::AtHeight(height))
+        };
+        let add = ExecuteMsg::AddPayments {
+            schedule: vec![payment(5, 1), payment(3, current_height + 10)],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: Some(true),
+        };
+        app.execute_contract(owner, vest_addr.clone(), &add, &[])
+            .unwrap();
+
+        // Only the back-dated payment goes out
+        let balance = app.wrap().query_balance(&payee2, "ujuno").unwrap();
+        assert_eq!(balance.amount, Uint128::new(5));
+
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let paid: Vec<bool> = res.payments.iter().map(|p| p.paid).collect();
+        assert_eq!(paid, vec![true, false]);
+    }
+
+    #[test]
+    fn never_expires() {
+        let payment = native_payment(PAYEE2, 1, Expiration::Never {});
+        let msg = |allow_never: Option<bool>| InstantiateMsg {
+            allow_never,
+            ..instantiate_msg(vec![payment.clone()])
+        };
+
+        let mut deps = mock_dependencies(&[]);
+        let err =
+            instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg(None)).unwrap_err();
+        assert!(matches!(err, ContractError::NeverExpires {}));
+
+        let mut deps = mock_dependencies(&[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            msg(Some(true)),
+        )
+        .unwrap();
+        let add = ExecuteMsg::AddPayments {
+            schedule: vec![payment.clone()],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), add).unwrap();
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetPayments {}).unwrap();
+        let res: PaymentsResponse = from_binary(&res).unwrap();
+        assert_eq!(res.payments.len(), 2);
+    }
+
+    #[test]
+    fn never_expiring_milestone() {
+        let height = mock_env().block.height;
+        let payment = Payment {
+            milestones: Some(vec![
+                Milestone {
+                    time: Expiration::AtHeight(height + 1),
+                    percent: 50,
+                },
+                Milestone {
+                    time: Expiration::Never {},
+                    percent: 50,
+                },
+            ]),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(height + 1))
+        };
+        let msg = |allow_never: Option<bool>| InstantiateMsg {
+            allow_never,
+            ..instantiate_msg(vec![payment.clone()])
+        };
+
+        let mut deps = mock_dependencies(&[]);
+        let err =
+            instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg(None)).unwrap_err();
+        assert!(matches!(err, ContractError::NeverExpires {}));
+
+        let mut deps = mock_dependencies(&[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(OWNER, &[]),
+            msg(Some(true)),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn default_time_rejected() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        let msg = instantiate_msg(vec![]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let payment = native_payment(PAYEE2, 1, Default::default());
+        let add = |payment: Payment| ExecuteMsg::AddPayments {
+            schedule: vec![payment],
+            dedupe: None,
+            batch_id: None,
+            auto_pay: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            add(payment.clone()),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NeverExpires {}));
+
+        // An offset replaces the unset time
+        let offset = Payment {
+            time_offset: Some(Duration::Height(5)),
+            ..payment
+        };
+        execute(deps.as_mut(), env, mock_info(OWNER, &[]), add(offset)).unwrap();
+    }
+
+    #[test]
+    fn get_state() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = |amount: u128, height: u64| {
+            native_payment(PAYEE2, amount, Expiration::AtHeight(height))
+        };
+        let height = env.block.height;
+        let msg = instantiate_msg(vec![
+            payment(1, height),
+            payment(2, height + 5),
+            payment(3, height + 10),
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        let pay = ExecuteMsg::Pay { limit: None };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), pay).unwrap();
+
+        let msg = QueryMsg::GetState {
+            start_after: Some(1),
+            limit: Some(1),
+        };
+        let res: StateResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::GetConfig {}).unwrap())
+                .unwrap();
+        let next_unlock: NextUnlockResponse =
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::NextUnlock {}).unwrap())
+                .unwrap();
+        let balances: BalancesResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::GetBalances {}).unwrap()).unwrap();
+
+        assert_eq!(res.config, config);
+        assert_eq!(
+            res.payments.iter().map(|p| p.id).collect::<Vec<u64>>(),
+            vec![2]
+        );
+        assert_eq!(res.next_unlock, next_unlock.next_unlock);
+        assert_eq!(res.next_unlock, Some(Expiration::AtHeight(height + 5)));
+        assert_eq!(res.outstanding, balances.outstanding);
+        assert_eq!(res.outstanding.native, coins(5, "ujuno"));
+    }
+
+    #[test]
+    fn rescue_token() {
+        let mut app = mock_app();
+
+        let (owner, funder, payee2, payee3) = get_accounts();
+
+        let cw20_addr = instantiate_cw20(&mut app);
+        let stray_addr = instantiate_cw20(&mut app);
+        let stray = Cw20Contract(stray_addr.clone());
+        let current_height = app.block_info().height;
 
+        let payment = cw20_payment(
+            payee2.clone(),
+            5,
+            &cw20_addr,
+            Expiration::AtHeight(current_height + 10),
+        );
+        let vest_addr = instantiate_vest(&mut app, vec![payment]);
         fund_vest_contract(
             &mut app,
             vest_addr.clone(),
             cw20_addr.clone(),
             funder.clone(),
-            Uint128::new(1),
+            Uint128::new(5),
+        );
+        // Sent by mistake
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            stray_addr.clone(),
+            funder,
+            Uint128::new(7),
         );
 
-        let owner_balance = |app: &App<Empty>| cw20.balance(app, owner.clone()).unwrap().u128();
-        let initial_balance = owner_balance(&app);
-        let vest_balance = cw20.balance(&app, vest_addr.clone()).unwrap().u128();
-        assert_eq!(vest_balance, 1);
+        let rescue = |token: &Addr| ExecuteMsg::RescueToken {
+            token_address: Some(token.clone()),
+            denom: None,
+            to: payee3.clone(),
+        };
+        let err: ContractError = app
+            .execute_contract(payee2.clone(), vest_addr.clone(), &rescue(&stray_addr), &[])
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let err: ContractError = app
+            .execute_contract(owner.clone(), vest_addr.clone(), &rescue(&cw20_addr), &[])
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(err, ContractError::TokenCommitted {}));
+
+        let err: ContractError = app
+            .execute_contract(
+                owner.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::RescueToken {
+                    token_address: None,
+                    denom: None,
+                    to: payee3.clone(),
+                },
+                &[],
+            )
+            .unwrap_err()
+            .downcast()
+            .unwrap();
+        assert!(matches!(err, ContractError::InvalidRescue {}));
+
+        let initial = stray.balance(&app, payee3.clone()).unwrap().u128();
+        app.execute_contract(owner, vest_addr.clone(), &rescue(&stray_addr), &[])
+            .unwrap();
+        assert_eq!(stray.balance(&app, payee3).unwrap().u128(), initial + 7);
+        assert_eq!(stray.balance(&app, vest_addr.clone()).unwrap().u128(), 0);
+        assert_eq!(
+            Cw20Contract(cw20_addr)
+                .balance(&app, vest_addr)
+                .unwrap()
+                .u128(),
+            5
+        );
+    }
+
+    #[test]
+    fn payout_address_drops_callback() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = Payment {
+            on_receive: Some(to_binary(&ReceiverMsg::Stake {}).unwrap()),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        let msg = ExecuteMsg::SetPayoutAddress {
+            address: Some(Addr::unchecked(PAYEE3)),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(PAYEE2, &[]), msg).unwrap();
+
+        // A Stake callback on a plain wallet would fail the payout every time
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info(OWNER, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: PAYEE3.to_string(),
+                amount: coins(10, "ujuno"),
+            })
+        );
+    }
+
+    #[test]
+    fn set_payout_address() {
+        let mut app = mock_app();
+
+        let (owner, _funder, payee2, payee3) = get_accounts();
+        let rotated = Addr::unchecked("rotated");
+        let current_height = app.block_info().height;
+
+        let payment = native_payment(payee2.clone(), 2, Expiration::AtHeight(current_height + 1));
+        let vest_addr = instantiate_vest(
+            &mut app,
+            vec![
+                payment.clone(),
+                Payment {
+                    amount: Uint128::new(3),
+                    ..payment.clone()
+                },
+                Payment {
+                    recipient: payee3.clone(),
+                    ..payment.clone()
+                },
+                Payment {
+                    time: Expiration::AtHeight(current_height + 2),
+                    ..payment.clone()
+                },
+            ],
+        );
+        app.init_bank_balance(&vest_addr, coins(9, "ujuno"))
+            .unwrap();
 
-        // Payout vested tokens
         app.execute_contract(
-            _payee3.clone(),
+            payee2.clone(),
             vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+            &ExecuteMsg::SetPayoutAddress {
+                address: Some(rotated.clone()),
+            },
+            &[],
         )
         .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
 
-        // Assert payment is not executed twice
-        app.execute_contract(_payee3, vest_addr, &ExecuteMsg::Pay {}, &vec![])
+        let native = |app: &App, addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        app.update_block(next_block);
+        app.execute_contract(
+            owner.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::Pay { limit: None },
+            &[],
+        )
+        .unwrap();
+        // Both of payee2's payouts are redirected, payee3 is unaffected
+        assert_eq!(native(&app, &rotated), 5);
+        assert_eq!(native(&app, &payee2), 0);
+        assert_eq!(native(&app, &payee3), 2);
+
+        app.execute_contract(
+            payee2.clone(),
+            vest_addr.clone(),
+            &ExecuteMsg::SetPayoutAddress { address: None },
+            &[],
+        )
+        .unwrap();
+        app.update_block(next_block);
+        app.execute_contract(owner, vest_addr, &ExecuteMsg::Pay { limit: None }, &[])
             .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
+        assert_eq!(native(&app, &rotated), 5);
+        assert_eq!(native(&app, &payee2), 2);
     }
 
     #[test]
-    fn multiple_cw20_payment() {
-        let mut app = mock_app();
+    fn calc_limit_bounds() {
+        assert_eq!(calc_limit(None), DEFAULT_LIMIT as usize);
+        assert_eq!(calc_limit(Some(5)), 5);
+        assert_eq!(calc_limit(Some(MAX_LIMIT)), MAX_LIMIT as usize);
+        assert_eq!(calc_limit(Some(u32::MAX)), MAX_LIMIT as usize);
+    }
 
-        let (owner, funder, _payee2, _payee3) = get_accounts();
+    #[test]
+    fn recipients() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
 
-        let cw20_addr = instantiate_cw20(&mut app);
-        let cw20 = Cw20Contract(cw20_addr.clone());
+        let payment = |recipient: &str| {
+            native_payment(recipient, 10, Expiration::AtHeight(env.block.height + 1))
+        };
+        let split = Payment {
+            recipients: Some(vec![
+                (Addr::unchecked(PAYEE2), 5000),
+                (Addr::unchecked("payee0004"), 5000),
+            ]),
+            ..payment(PAYEE3)
+        };
+        let msg = instantiate_msg(vec![
+            payment(PAYEE3),
+            payment(PAYEE2),
+            payment(PAYEE2),
+            split,
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        let current_height = app.block_info().height;
+        let recipients = |start_after: Option<&str>, limit: Option<u32>| -> Vec<Addr> {
+            let msg = QueryMsg::Recipients {
+                start_after: start_after.map(Addr::unchecked),
+                limit,
+            };
+            let res: RecipientsResponse =
+                from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+            res.recipients
+        };
+        assert_eq!(
+            recipients(None, None),
+            vec![
+                Addr::unchecked(PAYEE2),
+                Addr::unchecked(PAYEE3),
+                Addr::unchecked("payee0004"),
+            ]
+        );
+        assert_eq!(
+            recipients(Some(PAYEE2), Some(1)),
+            vec![Addr::unchecked(PAYEE3)]
+        );
+    }
 
-        let payments = vec![
+    #[test]
+    fn outstanding_by_recipient() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE3, 4, Expiration::AtHeight(env.block.height + 10));
+        let token = Addr::unchecked("token");
+        let msg = instantiate_msg(vec![
+            payment.clone(),
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(1),
-                denom: cw20_addr.to_string(),
-                token_address: Some(cw20_addr.clone()),
-                time: Expiration::AtHeight(current_height + 1),
+                recipient: Addr::unchecked(PAYEE2),
+                amount: Uint128::new(10),
+                ..payment.clone()
             },
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(2),
-                denom: cw20_addr.to_string(),
-                token_address: Some(cw20_addr.clone()),
-                time: Expiration::AtHeight(current_height + 2),
+                denom: "uatom".to_string(),
+                ..payment.clone()
             },
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(2),
-                denom: cw20_addr.to_string(),
-                token_address: Some(cw20_addr.clone()),
-                time: Expiration::AtHeight(current_height + 2),
+                recipient: Addr::unchecked(PAYEE2),
+                amount: Uint128::new(6),
+                denom: String::new(),
+                token_address: Some(token.clone()),
+                ..payment.clone()
             },
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(5),
-                denom: cw20_addr.to_string(),
-                token_address: Some(cw20_addr.clone()),
-                time: Expiration::AtHeight(current_height + 3),
+                recipient: Addr::unchecked(OWNER),
+                ..payment.clone()
             },
-        ];
+            Payment {
+                recipient: Addr::unchecked("payee0004"),
+                ..payment.clone()
+            },
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        // A recipient with nothing left is walked past, not listed
+        let msg = ExecuteMsg::StopPayment {
+            id: 6,
+            refund: Some(false),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        let vest_addr = instantiate_vest(&mut app, payments);
+        let page = |start_after: Option<&str>, limit: Option<u32>| {
+            let msg = QueryMsg::OutstandingByRecipient {
+                start_after: start_after.map(Addr::unchecked),
+                limit,
+            };
+            let res: OutstandingByRecipientResponse =
+                from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+            (res.recipients, res.next_start_after)
+        };
+        let (all, next) = page(None, None);
+        assert_eq!(next, None);
+        assert_eq!(
+            all.iter().map(|r| r.recipient.as_str()).collect::<Vec<_>>(),
+            vec![OWNER, PAYEE2, PAYEE3]
+        );
+        assert_eq!(
+            all[1].outstanding,
+            Totals {
+                native: coins(10, "ujuno"),
+                cw20: vec![Cw20CoinVerified {
+                    address: token,
+                    amount: Uint128::new(6),
+                }],
+            }
+        );
+        assert_eq!(
+            all[2].outstanding.native,
+            vec![coin(4, "ujuno"), coin(4, "uatom")]
+        );
 
-        fund_vest_contract(
-            &mut app,
-            vest_addr.clone(),
-            cw20_addr.clone(),
-            funder.clone(),
-            Uint128::new(10),
+        let cursor = |addr: &str| Some(Addr::unchecked(addr));
+        assert_eq!(page(None, Some(2)), (all[..2].to_vec(), cursor(PAYEE2)));
+        assert_eq!(
+            page(Some(PAYEE2), Some(1)),
+            (all[2..].to_vec(), cursor(PAYEE3))
         );
+        // Scanning payee0004 uses up the page even though it is owed nothing
+        assert_eq!(page(Some(PAYEE3), Some(1)), (vec![], None));
+        assert_eq!(page(Some(PAYEE2), Some(2)), (all[2..].to_vec(), None));
+    }
 
-        let owner_balance = |app: &App<Empty>| cw20.balance(app, owner.clone()).unwrap().u128();
-        let initial_balance = owner_balance(&app);
-        let vest_balance = cw20.balance(&app, vest_addr.clone()).unwrap().u128();
-        assert_eq!(vest_balance, 10);
+    #[test]
+    fn removed_payment_not_found() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
 
-        // Payout vested tokens
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+        let payment = native_payment(PAYEE2, 4, Expiration::AtHeight(env.block.height + 10));
+        let msg = instantiate_msg(vec![payment.clone(), payment.clone()]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        // Drops payments 1 and 2, which were never paid
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::ReplaceSchedule {
+                schedule: vec![payment],
+            },
         )
         .unwrap();
 
-        assert_eq!(owner_balance(&app), initial_balance);
+        for id in [1, 2, 4] {
+            for msg in [
+                ExecuteMsg::StopPayment { id, refund: None },
+                ExecuteMsg::FinalizeStop { id },
+                ExecuteMsg::PausePayment { id },
+                ExecuteMsg::ResumePayment { id },
+                ExecuteMsg::Clawback { id },
+                ExecuteMsg::Revoke { id },
+            ] {
+                let err =
+                    execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+                assert!(
+                    matches!(err, ContractError::PaymentNotFound { id: missing } if missing == id)
+                );
+            }
+        }
+    }
 
-        // Update block and pay first payment
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
+    #[test]
+    fn claim_all() {
+        let mut app = mock_app();
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
+        let (owner, funder, payee2, payee3) = get_accounts();
 
-        // Update block and make 2nd and 3rd payments
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 5);
+        let cw20_addr = instantiate_cw20(&mut app);
+        let cw20 = Cw20Contract(cw20_addr.clone());
+        let current_height = app.block_info().height;
 
-        // Check second call does not make more payments
+        let payment = native_payment(payee2.clone(), 2, Expiration::AtHeight(current_height + 1));
+        let vest_addr = instantiate_vest(
+            &mut app,
+            vec![
+                payment.clone(),
+                Payment {
+                    amount: Uint128::new(3),
+                    ..payment.clone()
+                },
+                Payment {
+                    amount: Uint128::new(5),
+                    denom: String::new(),
+                    token_address: Some(cw20_addr.clone()),
+                    ..payment.clone()
+                },
+                // Not matured yet
+                Payment {
+                    time: Expiration::AtHeight(current_height + 10),
+                    ..payment.clone()
+                },
+                Payment {
+                    recipient: payee3.clone(),
+                    ..payment
+                },
+            ],
+        );
+        app.init_bank_balance(&vest_addr, coins(10, "ujuno"))
+            .unwrap();
+        fund_vest_contract(
+            &mut app,
+            vest_addr.clone(),
+            cw20_addr.clone(),
+            funder,
+            Uint128::new(5),
+        );
+        // Only payers may sweep, claiming is still open to recipients
         app.execute_contract(
-            _payee3.clone(),
+            owner,
             vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+            &ExecuteMsg::UpdateConfig {
+                payout_start: None,
+                schedule_locked: None,
+                payers: Some(Update::Set(vec![])),
+                ibc_timeout_seconds: None,
+                fee: None,
+                min_payout: None,
+                stop_grace: None,
+                payout_order: None,
+                expected_version: None,
+                keeper_reward: None,
+                owner: None,
+                enabled: None,
+            },
+            &[],
         )
         .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 5);
 
-        // Update block and make 4th payments
+        let initial_cw20 = cw20.balance(&app, payee2.clone()).unwrap().u128();
         app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 10);
+        let res = app
+            .execute_contract(
+                payee2.clone(),
+                vest_addr.clone(),
+                &ExecuteMsg::ClaimAll {},
+                &[],
+            )
+            .unwrap();
+        let claimed: ClaimableResponse = from_binary(&res.data.unwrap()).unwrap();
+        assert_eq!(
+            claimed,
+            ClaimableResponse {
+                native: coins(5, "ujuno"),
+                cw20: vec![Cw20CoinVerified {
+                    address: cw20_addr,
+                    amount: Uint128::new(5),
+                }],
+            }
+        );
+        let native = |addr: &Addr| {
+            app.wrap()
+                .query_balance(addr, "ujuno")
+                .unwrap()
+                .amount
+                .u128()
+        };
+        assert_eq!(native(&payee2), 5);
+        assert_eq!(native(&payee3), 0);
+        assert_eq!(cw20.balance(&app, payee2).unwrap().u128(), initial_cw20 + 5);
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 10);
+        let res: PaymentsResponse = app
+            .wrap()
+            .query_wasm_smart(&vest_addr, &QueryMsg::GetPayments {})
+            .unwrap();
+        let paid: Vec<bool> = res.payments.iter().map(|p| p.paid).collect();
+        assert_eq!(paid, vec![true, true, true, false, false]);
+    }
 
-        // Assert contract has spent all funds
-        let vest_balance = cw20.balance(&app, vest_addr.clone()).unwrap().u128();
-        assert_eq!(vest_balance, 0);
+    #[test]
+    fn require_approval() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = Payment {
+            require_approval: true,
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height))
+        };
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let mut run = |sender: &str, msg: ExecuteMsg| {
+            execute(deps.as_mut(), env.clone(), mock_info(sender, &[]), msg)
+        };
+        let err = run(PAYEE3, ExecuteMsg::Approve { id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // Matured but unapproved
+        let res = run(OWNER, ExecuteMsg::Pay { limit: None }).unwrap();
+        assert!(res.messages.is_empty());
+
+        // Only the owner has approved
+        run(OWNER, ExecuteMsg::Approve { id: 1 }).unwrap();
+        let res = run(OWNER, ExecuteMsg::Pay { limit: None }).unwrap();
+        assert!(res.messages.is_empty());
+
+        run(PAYEE2, ExecuteMsg::Approve { id: 1 }).unwrap();
+        let res = run(OWNER, ExecuteMsg::Pay { limit: None }).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.attributes,
+            vec![attr("method", "pay"), attr("paid_id", "1")]
+        );
+
+        let err = run(PAYEE2, ExecuteMsg::Approve { id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotActive {}));
+    }
+
+    #[test]
+    fn due_queries_skip_unapproved() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height));
+        let msg = instantiate_msg(vec![
+            Payment {
+                require_approval: true,
+                ..payment.clone()
+            },
+            Payment {
+                amount: Uint128::new(4),
+                ..payment
+            },
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        let approve = ExecuteMsg::Approve { id: 1 };
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), approve).unwrap();
+
+        // Only the owner approved payment 1, so all of them report payment 2 alone
+        let query_as = |msg: QueryMsg| query(deps.as_ref(), env.clone(), msg).unwrap();
+        let ids = |res: PaymentsResponse| res.payments.iter().map(|p| p.id).collect::<Vec<_>>();
+        let due: PaymentsResponse =
+            from_binary(&query_as(QueryMsg::DuePayments { at: None })).unwrap();
+        assert_eq!(ids(due), vec![2]);
+        let count: DueCountResponse = from_binary(&query_as(QueryMsg::DueCount {})).unwrap();
+        assert_eq!(count.count, 1);
+        let status = |status: PaymentStatus| -> Vec<u64> {
+            let msg = QueryMsg::GetPaymentsByStatus { status };
+            ids(from_binary(&query_as(msg)).unwrap())
+        };
+        assert_eq!(status(PaymentStatus::Claimable), vec![2]);
+        assert_eq!(status(PaymentStatus::Pending), vec![1]);
+        let msg = QueryMsg::Claimable {
+            recipient: Addr::unchecked(PAYEE2),
+        };
+        let claimable: ClaimableResponse = from_binary(&query_as(msg)).unwrap();
+        assert_eq!(claimable.native, coins(4, "ujuno"));
+        let simulated: SimulatePayResponse =
+            from_binary(&query_as(QueryMsg::SimulatePay {})).unwrap();
+        assert_eq!(
+            simulated.payouts.iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn payout_order() {
+        let env = mock_env();
+
+        let payment =
+            |amount: u128| native_payment(PAYEE2, amount, Expiration::AtHeight(env.block.height));
+        let paid_ids = |order: PayoutOrder| -> Vec<String> {
+            // Not enough to cover all four
+            let mut deps = mock_dependencies(&coins(5, "ujuno"));
+            let msg = instantiate_msg(vec![payment(3), payment(1), payment(4), payment(2)]);
+            instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+            let update = ExecuteMsg::UpdateConfig {
+                payout_start: None,
+                schedule_locked: None,
+                payers: None,
+                ibc_timeout_seconds: None,
+                fee: None,
+                min_payout: None,
+                stop_grace: None,
+                payout_order: Some(order),
+                expected_version: None,
+                keeper_reward: None,
+                owner: None,
+                enabled: None,
+            };
+            execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), update).unwrap();
+
+            let pay = ExecuteMsg::Pay { limit: None };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), pay).unwrap();
+            res.attributes
+                .into_iter()
+                .filter(|a| a.key == "paid_id")
+                .map(|a| a.value)
+                .collect()
+        };
+        assert_eq!(paid_ids(PayoutOrder::OldestFirst), vec!["1", "2"]);
+        assert_eq!(paid_ids(PayoutOrder::SmallestFirst), vec!["2", "4"]);
+        assert_eq!(paid_ids(PayoutOrder::LargestFirst), vec!["3", "2"]);
+    }
+
+    #[test]
+    fn pay_cursor_sorted() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment =
+            |amount: u128| native_payment(PAYEE2, amount, Expiration::AtHeight(env.block.height));
+        let msg = instantiate_msg(vec![payment(2), payment(1), payment(2), payment(2)]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+        CONFIG
+            .update(deps.as_mut().storage, |mut config| -> StdResult<_> {
+                config.payout_order = PayoutOrder::SmallestFirst;
+                Ok(config)
+            })
+            .unwrap();
+
+        let mut pay = || -> (Vec<u64>, u64) {
+            let msg = ExecuteMsg::Pay { limit: Some(2) };
+            let res = execute(deps.as_mut(), env.clone(), mock_info(PAYEE3, &[]), msg).unwrap();
+            let ids = res
+                .attributes
+                .into_iter()
+                .filter(|a| a.key == "paid_id")
+                .map(|a| a.value.parse().unwrap())
+                .collect();
+            (ids, LAST_SWEPT_ID.load(deps.as_ref().storage).unwrap())
+        };
+        // The cursor is the highest id paid, not the last one
+        assert_eq!(pay(), (vec![2, 1], 2));
+        assert_eq!(pay(), (vec![3, 4], 4));
+        assert_eq!(pay(), (vec![], 0));
     }
 
-    #[test]
-    fn single_native_payment() {
-        let mut app = mock_app();
+    #[test]
+    fn delay_payment() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 10));
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
+
+        let delay = |new_time: Expiration| ExecuteMsg::DelayPayment { id: 1, new_time };
+        let later = Expiration::AtHeight(env.block.height + 20);
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE2, &[]),
+            delay(later),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
 
-        let (owner, funder, _payee2, _payee3) = get_accounts();
+        for new_time in [
+            Expiration::AtHeight(env.block.height + 5),
+            Expiration::AtHeight(env.block.height + 10),
+            Expiration::AtTime(env.block.time.plus_seconds(100)),
+        ] {
+            let err = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(OWNER, &[]),
+                delay(new_time),
+            )
+            .unwrap_err();
+            assert!(matches!(err, ContractError::InvalidDelay {}));
+        }
 
-        let denom = String::from("ujuno");
-        let payments = vec![Payment {
-            recipient: owner.clone(),
-            amount: Uint128::new(1),
-            denom: denom.clone(),
-            token_address: None,
-            time: Expiration::AtHeight(1),
-        }];
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            delay(later),
+        )
+        .unwrap();
+        let res: PaymentsResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::GetPayments {}).unwrap()).unwrap();
+        assert_eq!(res.payments[0].payment.time, later);
+    }
 
-        let vest_addr = instantiate_vest(&mut app, payments);
+    #[test]
+    fn delay_payment_checks() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
+        let height = env.block.height;
 
-        // Fund vest contract
-        app.init_bank_balance(&vest_addr, vec![coin(1, denom.clone())]);
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(height + 10));
+        let msg = InstantiateMsg {
+            enforce_order: true,
+            ..instantiate_msg(vec![
+                payment.clone(),
+                Payment {
+                    time: Expiration::AtHeight(height + 20),
+                    ..payment.clone()
+                },
+                Payment {
+                    time: Expiration::AtHeight(height + 30),
+                    ..payment
+                },
+            ])
+        };
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        let owner_balance = |app: &App<Empty>| {
-            app.wrap()
-                .query_balance(owner.clone(), denom.clone())
-                .unwrap()
-                .amount
-                .u128()
+        let mut delay = |id: u64, height: u64| {
+            let msg = ExecuteMsg::DelayPayment {
+                id,
+                new_time: Expiration::AtHeight(height),
+            };
+            execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg)
         };
-        let initial_balance = owner_balance(&app);
+        // Delays can't pass the next payment, the last one has none
+        let err = delay(1, height + 21).unwrap_err();
+        assert!(matches!(err, ContractError::OutOfOrder {}));
+        delay(1, height + 20).unwrap();
+        delay(3, height + 100).unwrap();
 
-        // Payout vested tokens
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
+        // A payment being stopped is left to FinalizeStop
+        let mut state = load_payment(&deps.storage, 2).unwrap();
+        state.stopping = Some(PendingStop {
+            effective_at: Expiration::AtHeight(height + 5),
+            refund: true,
+        });
+        payments()
+            .save(deps.as_mut().storage, 2u64.into(), &state)
+            .unwrap();
+        let msg = ExecuteMsg::DelayPayment {
+            id: 2,
+            new_time: Expiration::AtHeight(height + 25),
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotActive {}));
 
-        // Assert payment is not executed twice
-        app.execute_contract(_payee3, vest_addr, &ExecuteMsg::Pay {}, &vec![])
+        CONFIG
+            .update(deps.as_mut().storage, |mut config| -> StdResult<_> {
+                config.schedule_locked = true;
+                Ok(config)
+            })
             .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
+        let msg = ExecuteMsg::DelayPayment {
+            id: 3,
+            new_time: Expiration::AtHeight(height + 200),
+        };
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::ScheduleLocked {}));
     }
 
     #[test]
-    fn multiple_native_payment() {
-        let mut app = mock_app();
-
-        let (owner, funder, _payee2, _payee3) = get_accounts();
+    fn split_payment() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
 
-        let cw20_addr = instantiate_cw20(&mut app);
-        let cw20 = Cw20Contract(cw20_addr.clone());
+        let time = Expiration::AtHeight(env.block.height + 10);
+        let payment = native_payment(PAYEE2, 10, time);
+        let msg = instantiate_msg(vec![payment.clone()]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        let current_height = app.block_info().height;
+        let early = Expiration::AtHeight(env.block.height + 1);
+        let split = |amount: u128| ExecuteMsg::SplitPayment {
+            id: 1,
+            amount: Uint128::new(amount),
+            time: early,
+        };
+        for amount in [0, 10, 11] {
+            let err = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(OWNER, &[]),
+                split(amount),
+            )
+            .unwrap_err();
+            assert!(matches!(err, ContractError::InvalidSplitAmount {}));
+        }
 
-        let denom = String::from("ujuno");
-        let payments = vec![
-            Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(1),
-                denom: denom.clone(),
-                token_address: None,
-                time: Expiration::AtHeight(current_height + 1),
-            },
-            Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(2),
-                denom: denom.clone(),
-                token_address: None,
-                time: Expiration::AtHeight(current_height + 2),
-            },
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), split(3)).unwrap();
+        let res: PaymentsResponse =
+            from_binary(&query(deps.as_ref(), env, QueryMsg::GetPayments {}).unwrap()).unwrap();
+        assert_eq!(res.payments.len(), 2);
+        assert_eq!(
+            res.payments[0].payment,
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(2),
-                denom: denom.clone(),
-                token_address: None,
-                time: Expiration::AtHeight(current_height + 2),
-            },
+                amount: Uint128::new(7),
+                ..payment.clone()
+            }
+        );
+        assert_eq!(res.payments[1].id, 2);
+        assert_eq!(
+            res.payments[1].payment,
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(5),
-                denom: denom.clone(),
-                token_address: None,
-                time: Expiration::AtHeight(current_height + 3),
-            },
-        ];
-
-        let vest_addr = instantiate_vest(&mut app, payments);
+                amount: Uint128::new(3),
+                time: early,
+                ..payment
+            }
+        );
+    }
 
-        // Fund vest contract
-        app.init_bank_balance(&vest_addr, vec![coin(10, denom.clone())]);
+    #[test]
+    fn split_payment_due_and_order() {
+        let mut deps = mock_dependencies(&[]);
+        let mut env = mock_env();
 
-        let owner_balance = |app: &App<Empty>| {
-            app.wrap()
-                .query_balance(owner.clone(), denom.clone())
-                .unwrap()
-                .amount
-                .u128()
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 10));
+        let msg = InstantiateMsg {
+            enforce_order: true,
+            ..instantiate_msg(vec![
+                payment.clone(),
+                Payment {
+                    time: Expiration::AtHeight(env.block.height + 20),
+                    ..payment
+                },
+            ])
         };
-        let initial_balance = owner_balance(&app);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        // Payout vested tokens
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+        let split = |id: u64, height: u64| ExecuteMsg::SplitPayment {
+            id,
+            amount: Uint128::new(3),
+            time: Expiration::AtHeight(height),
+        };
+        // The split would unlock before the latest payment
+        let height = env.block.height;
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            split(1, height + 1),
         )
-        .unwrap();
-
-        assert_eq!(owner_balance(&app), initial_balance);
-
-        // Update block and pay first payment
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+        .unwrap_err();
+        assert!(matches!(err, ContractError::OutOfOrder {}));
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            split(1, height + 20),
         )
         .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+        // A due payment belongs to its recipient and can't be split
+        env.block.height += 10;
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            split(1, height + 30),
         )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 1);
+        .unwrap_err();
+        assert!(matches!(err, ContractError::AlreadyVested {}));
+        let state = load_payment(&deps.storage, 1).unwrap();
+        assert_eq!(state.payment.amount, Uint128::new(7));
+    }
 
-        // Update block and make 2nd and 3rd payments
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 5);
+    #[test]
+    fn top_up_payment() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 5);
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 5));
+        let msg = instantiate_msg(vec![payment]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        // Update block and make 4th payments
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 10);
+        let top_up = |additional: u128| ExecuteMsg::TopUpPayment {
+            id: 1,
+            additional: Uint128::new(additional),
+        };
+        let err =
+            execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), top_up(0)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTopUp {}));
+        execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), top_up(5)).unwrap();
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+        env.block.height += 5;
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
         )
         .unwrap();
-        assert_eq!(owner_balance(&app), initial_balance + 10);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(cosmwasm_std::BankMsg::Send {
+                to_address: PAYEE2.to_string(),
+                amount: coins(15, "ujuno"),
+            })
+        );
+
+        let err = execute(deps.as_mut(), env, mock_info(OWNER, &[]), top_up(5)).unwrap_err();
+        assert!(matches!(err, ContractError::PaymentNotActive {}));
     }
 
     #[test]
-    fn native_and_token_payments() {
-        let mut app = mock_app();
-
-        let (owner, funder, _payee2, _payee3) = get_accounts();
+    fn top_up_partly_paid() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let mut env = mock_env();
 
-        let cw20_addr = instantiate_cw20(&mut app);
-        let cw20 = Cw20Contract(cw20_addr.clone());
+        let start = env.block.height + 1;
+        let msg = instantiate_msg(vec![Payment {
+            recurrence: Some(Recurrence {
+                interval: Duration::Height(10),
+                installments: 4,
+            }),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(start))
+        }]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        let current_height = app.block_info().height;
+        env.block.height = start;
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(PAYEE3, &[]),
+            ExecuteMsg::Pay { limit: None },
+        )
+        .unwrap();
 
-        let denom = String::from("ujuno");
-        let payments = vec![
-            Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(1),
-                denom: denom.clone(),
-                token_address: None,
-                time: Expiration::AtHeight(current_height + 1),
+        // Nothing is due, but one installment of 10 has already gone out
+        env.block.height += 1;
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::TopUpPayment {
+                id: 1,
+                additional: Uint128::new(5),
             },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InstallmentsPaid { id: 1 }));
+        let state = load_payment(&deps.storage, 1).unwrap();
+        assert_eq!(state.installments_paid, 1);
+        assert_eq!(state.payment.amount, Uint128::new(10));
+    }
+
+    #[test]
+    fn stats() {
+        let mut deps = mock_dependencies(&coins(1000, "ujuno"));
+        let env = mock_env();
+
+        let payment = native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height));
+        let token = Addr::unchecked("token");
+        let msg = instantiate_msg(vec![
+            payment.clone(),
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(2),
+                recipient: Addr::unchecked(PAYEE3),
+                amount: Uint128::new(6),
                 denom: String::new(),
-                token_address: Some(cw20_addr.clone()),
-                time: Expiration::AtHeight(current_height + 2),
-            },
-            Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(2),
-                denom: denom.clone(),
-                token_address: None,
-                time: Expiration::AtHeight(current_height + 2),
+                token_address: Some(token.clone()),
+                time: Expiration::AtHeight(env.block.height + 10),
+                ..payment.clone()
             },
             Payment {
-                recipient: owner.clone(),
-                amount: Uint128::new(5),
-                denom: String::new(),
-                token_address: Some(cw20_addr.clone()),
-                time: Expiration::AtHeight(current_height + 3),
+                amount: Uint128::new(4),
+                time: Expiration::AtHeight(env.block.height + 10),
+                ..payment
             },
-        ];
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        let vest_addr = instantiate_vest(&mut app, payments);
-
-        // Fund vest contract
-        app.init_bank_balance(&vest_addr, vec![coin(3, denom.clone())]);
-        fund_vest_contract(
-            &mut app,
-            vest_addr.clone(),
-            cw20_addr.clone(),
-            funder.clone(),
-            Uint128::new(7),
+        let stats = |deps: &cosmwasm_std::OwnedDeps<_, _, _>| -> StatsResponse {
+            from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::Stats {}).unwrap()).unwrap()
+        };
+        let tokens = |amount: u128| {
+            vec![Cw20CoinVerified {
+                address: token.clone(),
+                amount: Uint128::new(amount),
+            }]
+        };
+        let totals = |native: Vec<Coin>, cw20: Vec<Cw20CoinVerified>| Totals { native, cw20 };
+        assert_eq!(
+            stats(&deps),
+            StatsResponse {
+                total_scheduled: totals(coins(14, "ujuno"), tokens(6)),
+                total_paid: Totals::default(),
+                total_refunded: Totals::default(),
+                total_active: totals(coins(14, "ujuno"), tokens(6)),
+                distinct_recipients: 2,
+                distinct_denoms: 2,
+            }
         );
 
-        let owner_balance_cw20 =
-            |app: &App<Empty>| cw20.balance(app, owner.clone()).unwrap().u128();
-        let owner_balance_juno = |app: &App<Empty>| {
-            app.wrap()
-                .query_balance(owner.clone(), denom.clone())
-                .unwrap()
-                .amount
-                .u128()
+        let mut run = |msg: ExecuteMsg| {
+            execute(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
         };
-        let initial_balance_cw20 = owner_balance_cw20(&app);
-        let initial_balance_juno = owner_balance_juno(&app);
-
-        // Payout vested tokens
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-
-        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20);
-        assert_eq!(owner_balance_juno(&app), initial_balance_juno);
-
-        // Update block and pay first payment
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20);
-        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 1);
+        run(ExecuteMsg::Pay { limit: None });
+        run(ExecuteMsg::StopPayment {
+            id: 3,
+            refund: None,
+        });
+        let res = stats(&deps);
+        assert_eq!(res.total_paid, totals(coins(10, "ujuno"), vec![]));
+        assert_eq!(res.total_refunded, totals(coins(4, "ujuno"), vec![]));
+        assert_eq!(res.total_active, totals(vec![], tokens(6)));
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20);
-        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 1);
+        // Contracts from before stats were tracked rebuild them on migrate
+        STATS.remove(deps.as_mut().storage);
+        for recipient in [PAYEE2, PAYEE3] {
+            SEEN_RECIPIENTS.remove(deps.as_mut().storage, &Addr::unchecked(recipient));
+        }
+        migrate(deps.as_mut(), env.clone(), MigrateMsg { owner: None }).unwrap();
+        assert_eq!(stats(&deps), res);
 
-        // Update block and make 2nd and 3rd payments
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(OWNER, &[]),
+            ExecuteMsg::StopPayment {
+                id: 2,
+                refund: Some(false),
+            },
         )
         .unwrap();
-        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 2);
-        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+        let res = stats(&deps);
+        assert_eq!(res.total_refunded, totals(coins(4, "ujuno"), vec![]));
+        assert_eq!(res.total_active, Totals::default());
+        assert_eq!(res.total_scheduled, totals(coins(14, "ujuno"), tokens(6)));
+    }
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 2);
-        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+    #[test]
+    fn payments_by_token() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env();
 
-        // Update block and make 4th payments
-        app.update_block(next_block);
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 7);
-        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+        let payment = |token: Option<&str>| Payment {
+            denom: if token.is_some() { "" } else { "ujuno" }.to_string(),
+            token_address: token.map(Addr::unchecked),
+            ..native_payment(PAYEE2, 10, Expiration::AtHeight(env.block.height + 1))
+        };
+        let msg = instantiate_msg(vec![
+            payment(Some("token0001")),
+            payment(Some("token0002")),
+            payment(None),
+            payment(Some("token0001")),
+            payment(Some("token0002")),
+            payment(Some("token0001")),
+        ]);
+        instantiate(deps.as_mut(), env.clone(), mock_info(OWNER, &[]), msg).unwrap();
 
-        // Check second call does not make more payments
-        app.execute_contract(
-            _payee3.clone(),
-            vest_addr.clone(),
-            &ExecuteMsg::Pay {},
-            &vec![],
-        )
-        .unwrap();
-        assert_eq!(owner_balance_cw20(&app), initial_balance_cw20 + 7);
-        assert_eq!(owner_balance_juno(&app), initial_balance_juno + 3);
+        let ids = |token: &str, start_after: Option<u64>, limit: Option<u32>| {
+            let msg = QueryMsg::PaymentsByToken {
+                token_address: Addr::unchecked(token),
+                start_after,
+                limit,
+            };
+            let res: PaymentsResponse =
+                from_binary(&query(deps.as_ref(), env.clone(), msg).unwrap()).unwrap();
+            res.payments.iter().map(|p| p.id).collect::<Vec<u64>>()
+        };
+        assert_eq!(ids("token0001", None, None), vec![1, 4, 6]);
+        assert_eq!(ids("token0002", None, None), vec![2, 5]);
+        assert_eq!(ids("token0001", Some(1), Some(1)), vec![4]);
+        assert_eq!(ids("token0003", None, None), Vec::<u64>::new());
     }
 }
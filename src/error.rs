@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,8 +8,147 @@ pub enum ContractError {
 
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("Config changed since the expected version")]
+    StaleConfig {},
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
-    #[error("Payment not found")]
-    PaymentNotFound {},
+    #[error("Payment {id} not found")]
+    PaymentNotFound { id: u64 },
+
+    #[error("Grant {grant_id} has no active payments")]
+    GrantNotFound { grant_id: u64 },
+
+    #[error("Address is not an admin")]
+    AdminNotFound {},
+
+    #[error("Admins cannot be empty or the contract itself")]
+    InvalidOwner {},
+
+    #[error("A contract without a config needs an owner to migrate")]
+    MissingOwner {},
+
+    #[error("Cannot remove the last admin")]
+    CannotRemoveLastAdmin {},
+
+    #[error("Overflow")]
+    Overflow {},
+
+    #[error("Payouts are disabled")]
+    PayoutsDisabled {},
+
+    #[error("Payouts have not started")]
+    PayoutsNotStarted {},
+
+    #[error("Invalid recurrence")]
+    InvalidRecurrence {},
+
+    #[error("Invalid linear schedule")]
+    InvalidLinearSchedule {},
+
+    #[error("Payment unlocks before the payment preceding it")]
+    OutOfOrder {},
+
+    #[error("Recipient weights must be nonzero and sum to 10000")]
+    InvalidSplit {},
+
+    #[error("Payment {id} has an invalid token address")]
+    InvalidTokenAddress { id: u64 },
+
+    #[error("Native payments need a denom when there is no default denom")]
+    MissingDenom {},
+
+    #[error("Invalid denom")]
+    InvalidDenom {},
+
+    #[error("Exactly one of token_address and denom must be set")]
+    InvalidRescue {},
+
+    #[error("Token is owed to active payments")]
+    TokenCommitted {},
+
+    #[error("Generated payment id is already in use")]
+    IdCollision {},
+
+    #[error("Payment does not require approval")]
+    ApprovalNotRequired {},
+
+    #[error("Payments can only be delayed to a later time in the same unit")]
+    InvalidDelay {},
+
+    #[error("Split amount must be less than the payment and only one-off payments can be split")]
+    InvalidSplitAmount {},
+
+    #[error("Top up amount must be nonzero")]
+    InvalidTopUp {},
+
+    #[error("Payment {id} has paid installments and can't be topped up")]
+    InstallmentsPaid { id: u64 },
+
+    #[error("Payment time never expires")]
+    NeverExpires {},
+
+    #[error("Denom is not allowed")]
+    DenomNotAllowed {},
+
+    #[error("Cw20 payments must leave denom empty")]
+    DenomWithToken {},
+
+    #[error("Total supply of {token} is {supply}, expected at least {expected}")]
+    Cw20SupplyTooLow {
+        token: String,
+        supply: Uint128,
+        expected: Uint128,
+    },
+
+    #[error("Invalid milestones")]
+    InvalidMilestones {},
+
+    #[error("Extra coins require a native payment with distinct denoms")]
+    InvalidExtraCoins {},
+
+    #[error("IBC payments must be a single native coin without on_receive")]
+    InvalidIbcPayment {},
+
+    #[error("Payment is already paused")]
+    AlreadyPaused {},
+
+    #[error("Payment is not paused")]
+    NotPaused {},
+
+    #[error("Fee cannot exceed 10000 basis points")]
+    InvalidFee {},
+
+    #[error("Payment has not expired")]
+    NotExpired {},
+
+    #[error("Payment already paid or stopped")]
+    PaymentNotActive {},
+
+    #[error("Payment has already vested")]
+    AlreadyVested {},
+
+    #[error("Payment is active and can no longer be stopped")]
+    PaymentActive {},
+
+    #[error("Schedule is locked")]
+    ScheduleLocked {},
+
+    #[error("Memo is longer than {max} characters")]
+    MemoTooLong { max: usize },
+
+    #[error("Payment is not being stopped")]
+    NotStopping {},
+
+    #[error("Stop grace period has not passed")]
+    GraceNotElapsed {},
+
+    #[error("Contract has been terminated")]
+    Terminated {},
+
+    #[error("Batch was already added")]
+    DuplicateBatch {},
+
+    #[error("Cannot add more than {max} payments at once")]
+    TooManyPayments { max: usize },
 }
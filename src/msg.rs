@@ -1,27 +1,280 @@
 use crate::state::PaymentState;
-use cosmwasm_std::{Addr, Uint128};
-use cw0::Expiration;
+use cosmwasm_std::{Addr, Binary, Coin, Uint128};
+use cw0::{Duration, Expiration};
+use cw20::Cw20CoinVerified;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
+    // Manages payments and config. The instantiator becomes the owner, which
+    // only receives refunds unless it is also the admin
+    pub admin: Addr,
     pub schedule: Vec<Payment>,
+    // Require payment times to never decrease, across later additions too
+    pub enforce_order: bool,
+    // Fails instantiation if any cw20 in the schedule has a smaller total supply
+    pub expected_cw20_total: Option<Uint128>,
+    // Denom for native payments that leave theirs empty
+    pub default_denom: Option<String>,
+    // Only these native denoms can be scheduled when set
+    pub allowed_denoms: Option<Vec<String>>,
+    // Accept payments timed Expiration::Never, which only a stop can release
+    pub allow_never: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Payment {
     pub recipient: Addr,
     pub amount: Uint128,
+    // Native denom, left empty for cw20 payments or to use the default denom
     pub denom: String,
     pub token_address: Option<Addr>,
+    // When the payment unlocks. Expiration::default() is Never, which is
+    // rejected unless the contract allows it, so it must be set explicitly
     pub time: Expiration,
+    // Replaces time with this long after the block the payment is added in, for
+    // schedules written before the contract exists. Not valid with milestones
+    pub time_offset: Option<Duration>,
+    // Unlocks amount every interval after time, instead of once
+    pub recurrence: Option<Recurrence>,
+    // Unlocks percentages of amount at each milestone, instead of all at time.
+    // The first milestone must be at time and percentages must sum to 100
+    pub milestones: Option<Vec<Milestone>>,
+    // Other native coins released alongside amount on every installment. Only
+    // valid for native payments, with denoms distinct from denom
+    pub extra_coins: Option<Vec<Coin>>,
+    // Sends payouts over this ICS20 channel to a recipient on the remote chain.
+    // Only valid for a single native coin without on_receive
+    pub ibc_channel: Option<String>,
+    // After this the owner can claw back whatever is still unpaid
+    pub expiry: Option<Expiration>,
+    // Before this the payment can be stopped freely, after it only Terminate and
+    // Clawback can end it
+    pub active_from: Option<Expiration>,
+    // Free form label, at most MAX_MEMO_LENGTH characters
+    pub memo: Option<String>,
+    // Executed on a contract recipient along with the payout. Native payouts
+    // call the recipient with this message, cw20 payouts use Send with it
+    pub on_receive: Option<Binary>,
+    // Groups payments into one grant, e.g. a native and a cw20 half, so they
+    // can be stopped together
+    pub grant_id: Option<u64>,
+    // Splits every payout among these addresses by weight out of 10000, instead
+    // of paying recipient. Cannot be combined with ibc_channel or on_receive
+    pub recipients: Option<Vec<(Addr, u16)>>,
+    // Pay skips the payment until a smart query of this contract returns expected
+    pub condition: Option<Condition>,
+    // Pay skips the payment until both the owner and recipient have approved it
+    #[serde(default)]
+    pub require_approval: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Recurrence {
+    pub interval: Duration,
+    pub installments: u32,
+}
+
+// Change to an optional config value, Clear unsets it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Update<T> {
+    Set(T),
+    Clear {},
+}
+
+impl<T> Update<T> {
+    pub fn into_option(self) -> Option<T> {
+        match self {
+            Update::Set(value) => Some(value),
+            Update::Clear {} => None,
+        }
+    }
+}
+
+// Which due payments Pay covers first when the balance can't cover them all.
+// Amounts are compared as is, whatever their denom or token
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutOrder {
+    #[default]
+    OldestFirst,
+    SmallestFirst,
+    LargestFirst,
+}
+
+// Share of each payout in basis points sent to collector
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Fee {
+    pub bps: u16,
+    pub collector: Addr,
+}
+
+// Compared byte for byte, so expected must be the exact json the contract returns
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Condition {
+    pub contract: Addr,
+    pub query: Binary,
+    pub expected: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Milestone {
+    pub time: Expiration,
+    pub percent: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    Pay {},
+    // Pays due payments, at most limit of them when set
+    Pay {
+        limit: Option<u32>,
+    },
+    // Pays everything due to the sender as recipient, whoever the payers are.
+    // Data is a ClaimableResponse with the amounts paid out
+    ClaimAll {},
+    // Accepts native funds sent along, then runs Pay when then_pay is set
+    Fund {
+        then_pay: bool,
+    },
+    AddAdmin {
+        address: Addr,
+    },
+    RemoveAdmin {
+        address: Addr,
+    },
+    // With dedupe, payments matching an active one on recipient, amount, denom,
+    // token and time are skipped. A batch_id can only be used once, so retried
+    // submissions are rejected. With auto_pay, added payments that are already
+    // due are paid right away, once payouts have started
+    AddPayments {
+        schedule: Vec<Payment>,
+        dedupe: Option<bool>,
+        batch_id: Option<String>,
+        auto_pay: Option<bool>,
+    },
+    // Adds num_installments payments of total split evenly, the last one taking
+    // any remainder, the first unlocking at start and the last at end
+    AddLinearSchedule {
+        recipient: Addr,
+        total: Uint128,
+        denom: String,
+        token_address: Option<Addr>,
+        start: Expiration,
+        end: Expiration,
+        num_installments: u32,
+    },
+    // Fields left None are unchanged. schedule_locked cannot be unset once locked.
+    // payers restricts Pay to those addresses and the owner, cleared allows anyone.
+    // ibc_timeout_seconds falls back to DEFAULT_IBC_TIMEOUT_SECONDS when cleared.
+    // fee is taken from every payout, clearing disables it. keeper_reward is sent
+    // to Pay callers that pay something, only out of funds no payment is owed
+    UpdateConfig {
+        // Owner only, hands the contract to a new owner
+        owner: Option<Addr>,
+        // Pay and ClaimAll are refused while false
+        enabled: Option<bool>,
+        payout_start: Option<Update<Expiration>>,
+        schedule_locked: Option<bool>,
+        payers: Option<Update<Vec<Addr>>>,
+        ibc_timeout_seconds: Option<Update<u64>>,
+        fee: Option<Update<Fee>>,
+        min_payout: Option<Update<Uint128>>,
+        stop_grace: Option<Update<Duration>>,
+        payout_order: Option<PayoutOrder>,
+        keeper_reward: Option<Update<Coin>>,
+        // Fails with StaleConfig unless this is the current config version
+        expected_version: Option<u64>,
+    },
+    // Stops a payment, refunding its remainder to the owner unless refund is false.
+    // With a stop grace period it keeps paying until FinalizeStop
+    StopPayment {
+        id: u64,
+        refund: Option<bool>,
+    },
+    // Stops a payment whose stop grace period has passed, refunding it if the
+    // stop asked to
+    FinalizeStop {
+        id: u64,
+    },
+    // Stops every active payment in the grant. Data is a StopPaymentResponse
+    // per stopped payment
+    StopGrant {
+        grant_id: u64,
+        refund: Option<bool>,
+    },
+    // Refunds and drops every active payment, then adds schedule. Payments with
+    // installments already paid are kept as stopped. Data is AddPaymentsResponse
+    ReplaceSchedule {
+        schedule: Vec<Payment>,
+    },
+    // Owner only. Stops every active payment, including installments already
+    // due, and sends the whole balance to the owner. Every later execute is
+    // rejected
+    Terminate {},
+    // Owner only. Sends the whole balance of a token or denom no active payment
+    // uses to an address. Exactly one of token_address and denom must be set
+    RescueToken {
+        token_address: Option<Addr>,
+        denom: Option<String>,
+        to: Addr,
+    },
+    // Sends the sender's future payouts to address instead, as plain transfers
+    // without any on_receive callback. None goes back to paying the recipient
+    SetPayoutAddress {
+        address: Option<Addr>,
+    },
+    // Approves a payment that requires approval, called by its owner or recipient
+    Approve {
+        id: u64,
+    },
+    // Freezes a payment without refunding it, Pay skips it until resumed
+    PausePayment {
+        id: u64,
+    },
+    ResumePayment {
+        id: u64,
+    },
+    // Owner only, stops an expired payment and refunds everything unpaid to the owner
+    Clawback {
+        id: u64,
+    },
+    // Ends a payment at the current block for a good leaver. Unlocked installments
+    // stay claimable by the recipient, the rest is refunded to the owner
+    Revoke {
+        id: u64,
+    },
+    // Owner only, moves the unlock time of a payment with nothing due yet later.
+    // Not valid for milestone payments or a locked schedule, and with
+    // enforce_order not past the next payment
+    DelayPayment {
+        id: u64,
+        new_time: Expiration,
+    },
+    // Owner only, moves amount of a one-off payment with nothing due yet into a
+    // new payment unlocking at time, the rest stays on the original schedule
+    SplitPayment {
+        id: u64,
+        amount: Uint128,
+        time: Expiration,
+    },
+    // Owner only, raises the amount of a payment with nothing due yet. For
+    // recurring payments this is the amount of every installment, so only
+    // payments with no installments paid can be topped up
+    TopUpPayment {
+        id: u64,
+        additional: Uint128,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    // Owner and admin of a contract migrated from a release without a config,
+    // ignored otherwise
+    #[serde(default)]
+    pub owner: Option<Addr>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -29,6 +282,102 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     // GetCount returns the current count as a json-encoded number
     GetPayments {},
+    GetConfig {},
+    // Claimable returns the total of all due but unpaid payments for recipient
+    // that Pay would send, ignoring contract balance
+    Claimable {
+        recipient: Addr,
+    },
+    GetPaymentsByStatus {
+        status: PaymentStatus,
+    },
+    // DuePayments returns the payments Pay would sweep at the given point, ignoring
+    // contract balance. Defaults to the current block
+    DuePayments {
+        at: Option<Expiration>,
+    },
+    // DueCount returns how many payments DuePayments would return at the current
+    // block, so keepers can size Pay's limit
+    DueCount {},
+    // NextUnlock returns the earliest future unlock of an active payment
+    NextUnlock {},
+    // GetPaymentsCount returns stored counters without scanning payments. total
+    // is the number of ids issued, so it includes payments ReplaceSchedule
+    // removed and is not the number of payments stored
+    GetPaymentsCount {},
+    // SimulatePay returns the transfers Pay would send at the current block,
+    // fees included, without paying. The keeper reward depends on the caller
+    // and is left out
+    SimulatePay {},
+    // PreviewStop returns what StopPayment with refund would send back for id
+    PreviewStop {
+        id: u64,
+    },
+    // RecipientSummary totals what recipient has been paid, is still owed and lost
+    // to stopped payments
+    RecipientSummary {
+        recipient: Addr,
+    },
+    // PaymentsInRange returns payments whose time is within from..=to. Payments timed
+    // in a different unit than the bounds never match
+    PaymentsInRange {
+        from: Expiration,
+        to: Expiration,
+    },
+    // IsAdmin returns whether address is the owner or an admin, so a frontend
+    // knows to show it the owner only or admin actions
+    IsAdmin {
+        address: Addr,
+    },
+    // GetBalances returns what the contract holds of every denom and token in the
+    // schedule, next to what active payments still owe
+    GetBalances {},
+    // GetPaymentsGrouped returns every payment split into active, paid and stopped
+    GetPaymentsGrouped {},
+    // Progress returns how much of the schedule has been paid out. Amounts of all
+    // denoms and tokens are added together, so it suits single asset schedules
+    Progress {},
+    // GetState returns config, a page of payments by id, the next unlock and what
+    // active payments still owe, to load a frontend in one query
+    GetState {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    // OutstandingByRecipient returns what active payments still owe each
+    // recipient, paginated by recipient address, shorter addresses first.
+    // Scans at most limit recipients, so a page can be short or empty with
+    // more to come, continue from next_start_after
+    OutstandingByRecipient {
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    },
+    // Recipients returns every address ever scheduled a payment or a share of
+    // one, each once, paginated by address
+    Recipients {
+        start_after: Option<Addr>,
+        limit: Option<u32>,
+    },
+    // PaymentsByToken returns a page of payments, by id, paid in the given cw20
+    PaymentsByToken {
+        token_address: Addr,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    // Stats returns running totals of everything scheduled, paid, refunded and
+    // still owed, without scanning payments
+    Stats {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    // Active with nothing due yet, or with due installments Pay holds back
+    // for approval, a condition or a pause
+    Pending,
+    Paid,
+    Stopped,
+    // Active with an amount due now that Pay would send
+    Claimable,
 }
 
 // We define a custom struct for each query response
@@ -36,3 +385,153 @@ pub enum QueryMsg {
 pub struct PaymentsResponse {
     pub payments: Vec<PaymentState>,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub owner: Addr,
+    pub admins: Vec<Addr>,
+    pub payout_start: Option<Expiration>,
+    pub schedule_locked: bool,
+    pub payers: Option<Vec<Addr>>,
+    pub enforce_order: bool,
+    pub ibc_timeout_seconds: u64,
+    pub fee_bps: u16,
+    pub fee_collector: Option<Addr>,
+    pub min_payout: Option<Uint128>,
+    pub default_denom: Option<String>,
+    pub stop_grace: Option<Duration>,
+    pub allowed_denoms: Option<Vec<String>>,
+    pub allow_never: bool,
+    pub terminated: bool,
+    pub payout_order: PayoutOrder,
+    pub version: u64,
+    pub keeper_reward: Option<Coin>,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClaimableResponse {
+    pub native: Vec<Coin>,
+    pub cw20: Vec<Cw20CoinVerified>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Totals {
+    pub native: Vec<Coin>,
+    pub cw20: Vec<Cw20CoinVerified>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RecipientSummaryResponse {
+    pub total_paid: Totals,
+    pub total_pending: Totals,
+    pub total_stopped: Totals,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct GroupedPaymentsResponse {
+    pub active: Vec<PaymentState>,
+    pub paid: Vec<PaymentState>,
+    pub stopped: Vec<PaymentState>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StateResponse {
+    pub config: ConfigResponse,
+    pub payments: Vec<PaymentState>,
+    pub next_unlock: Option<Expiration>,
+    pub outstanding: Totals,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RecipientOutstanding {
+    pub recipient: Addr,
+    pub outstanding: Totals,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OutstandingByRecipientResponse {
+    pub recipients: Vec<RecipientOutstanding>,
+    // Where the scan stopped, pass it as start_after for the next page. None
+    // once every recipient has been scanned
+    pub next_start_after: Option<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RecipientsResponse {
+    pub recipients: Vec<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StatsResponse {
+    pub total_scheduled: Totals,
+    pub total_paid: Totals,
+    pub total_refunded: Totals,
+    pub total_active: Totals,
+    pub distinct_recipients: u64,
+    // Native denoms and cw20 tokens ever scheduled
+    pub distinct_denoms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProgressResponse {
+    // Everything the schedule pays, leaving out the unpaid part of stopped payments
+    pub total: Uint128,
+    pub released: Uint128,
+    pub percent_bps: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BalancesResponse {
+    pub balances: Totals,
+    pub outstanding: Totals,
+}
+
+// Returned as data from StopPayment and by PreviewStop
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StopPaymentResponse {
+    pub id: u64,
+    pub refunded: Uint128,
+    pub denom: String,
+    pub token_address: Option<Addr>,
+}
+
+// Returned as data from AddPayments, ids are in schedule order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AddPaymentsResponse {
+    pub ids: Vec<u64>,
+    pub skipped: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NextUnlockResponse {
+    pub next_unlock: Option<Expiration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PaymentsCountResponse {
+    // Ids issued, removed payments included
+    pub total: u64,
+    pub paid: u64,
+    pub stopped: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DueCountResponse {
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Payout {
+    pub id: u64,
+    pub recipient: Addr,
+    pub amount: Uint128,
+    pub denom: String,
+    pub token_address: Option<Addr>,
+    pub extra_coins: Option<Vec<Coin>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulatePayResponse {
+    pub payouts: Vec<Payout>,
+}
@@ -1,24 +1,287 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::msg::Payment;
-use cosmwasm_std::{StdResult, Storage};
-use cw_storage_plus::{Item, Map, U64Key};
+use crate::msg::{Payment, PayoutOrder, Totals};
+use crate::ContractError;
+use cosmwasm_std::{Addr, Coin, Order, StdResult, Storage, Uint128};
+use cw0::{Duration, Expiration};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex, U64Key, U8Key};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    // Instantiator of the contract and the default recipient of refunds
+    pub owner: Addr,
+    // Addresses allowed to perform privileged actions, never empty
+    pub admins: Vec<Addr>,
+    // Payouts are rejected until this expires
+    pub payout_start: Option<Expiration>,
+    // No payments can be added once locked
+    pub schedule_locked: bool,
+    // Addresses allowed to trigger payouts besides the owner, anyone when None
+    pub payers: Option<Vec<Addr>>,
+    // Each new payment must unlock no earlier than the one before it
+    pub enforce_order: bool,
+    // How long IBC payouts have to arrive, DEFAULT_IBC_TIMEOUT_SECONDS when None
+    pub ibc_timeout_seconds: Option<u64>,
+    // Basis points of every payout sent to fee_collector, which is set when nonzero
+    pub fee_bps: u16,
+    pub fee_collector: Option<Addr>,
+    // Partial payouts below this wait until enough has unlocked, final ones
+    // always go out
+    pub min_payout: Option<Uint128>,
+    // Filled into native payments stored without a denom
+    pub default_denom: Option<String>,
+    // How long stopped payments keep paying out before FinalizeStop can stop them
+    pub stop_grace: Option<Duration>,
+    // Native denoms payments may use, any when None
+    pub allowed_denoms: Option<Vec<String>>,
+    // Payments may be timed Expiration::Never
+    #[serde(default)]
+    pub allow_never: bool,
+    // Set by Terminate, after which nothing can be executed
+    #[serde(default)]
+    pub terminated: bool,
+    #[serde(default)]
+    pub payout_order: PayoutOrder,
+    // Bumped by every admin change to the config, so UpdateConfig can refuse
+    // to apply on top of a change it hasn't seen
+    #[serde(default)]
+    pub version: u64,
+    // Sent to whoever calls Pay when it pays something, out of funds no
+    // payment is owed
+    #[serde(default)]
+    pub keeper_reward: Option<Coin>,
+    // Pay and ClaimAll are refused while false
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl Config {
+    // Config with owner and admin and every setting off
+    pub fn new(owner: Addr, admin: Addr) -> Self {
+        Config {
+            owner,
+            admins: vec![admin],
+            payout_start: None,
+            schedule_locked: false,
+            payers: None,
+            enforce_order: false,
+            ibc_timeout_seconds: None,
+            fee_bps: 0,
+            fee_collector: None,
+            min_payout: None,
+            default_denom: None,
+            stop_grace: None,
+            allowed_denoms: None,
+            allow_never: false,
+            terminated: false,
+            payout_order: PayoutOrder::default(),
+            version: 0,
+            keeper_reward: None,
+            enabled: true,
+        }
+    }
+
+    pub fn is_admin(&self, addr: &Addr) -> bool {
+        self.admins.iter().any(|a| a == addr)
+    }
+
+    pub fn ibc_timeout_seconds(&self) -> u64 {
+        self.ibc_timeout_seconds
+            .unwrap_or(DEFAULT_IBC_TIMEOUT_SECONDS)
+    }
+
+    pub fn is_payer(&self, addr: &Addr) -> bool {
+        match &self.payers {
+            Some(payers) => *addr == self.owner || payers.contains(addr),
+            None => true,
+        }
+    }
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+
+pub const DEFAULT_IBC_TIMEOUT_SECONDS: u64 = 600;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct PaymentState {
     pub payment: Payment,
     pub paid: bool,
     pub id: u64,
+    // Fields below were added after the first release, so payments stored by
+    // it load with their defaults
+    #[serde(default)]
+    pub installments_paid: u32,
+    #[serde(default)]
+    pub stopped: bool,
+    // Paused payments are skipped by Pay until resumed
+    #[serde(default)]
+    pub paused: bool,
+    // Block the final installment was paid at, in the same unit as payment.time
+    #[serde(default)]
+    pub paid_at: Option<Expiration>,
+    // Set by a stop during the stop grace period, which FinalizeStop completes
+    #[serde(default)]
+    pub stopping: Option<PendingStop>,
+    // Payouts that failed in a row, at MAX_PAYOUT_FAILURES the payment is paused
+    #[serde(default)]
+    pub fail_count: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingStop {
+    pub effective_at: Expiration,
+    pub refund: bool,
+}
+
+pub const PAYMENT_COUNT: Item<u64> = Item::new("payment_count");
+
+// Key the payment counter was stored under before it was renamed
+pub const LEGACY_PAYMENT_COUNT: Item<u64> = Item::new("proposal_count");
+
+pub struct PaymentIndexes<'a> {
+    pub recipient: MultiIndex<'a, (Vec<u8>, Vec<u8>), PaymentState>,
+    // Keyed by 1 for paid payments and 0 for the rest
+    pub paid: MultiIndex<'a, (U8Key, Vec<u8>), PaymentState>,
+}
+
+impl<'a> IndexList<PaymentState> for PaymentIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<PaymentState>> + '_> {
+        let v: Vec<&dyn Index<PaymentState>> = vec![&self.recipient, &self.paid];
+        Box::new(v.into_iter())
+    }
+}
+
+// Payments by id, indexed by recipient and paid status. Stored under the same
+// namespace as the plain map it replaced
+pub fn payments<'a>() -> IndexedMap<'a, U64Key, PaymentState, PaymentIndexes<'a>> {
+    let indexes = PaymentIndexes {
+        recipient: MultiIndex::new(
+            |p: &PaymentState, pk| (p.payment.recipient.as_bytes().to_vec(), pk),
+            "payments",
+            "payments__recipient",
+        ),
+        paid: MultiIndex::new(
+            |p: &PaymentState, pk| (U8Key::new(p.paid as u8), pk),
+            "payments",
+            "payments__paid",
+        ),
+    };
+    IndexedMap::new("payments", indexes)
 }
 
-pub const PAYMENT_COUNT: Item<u64> = Item::new("proposal_count");
+// Number of payments fully paid out and stopped, kept in step with payments()
+pub const PAID_COUNT: Item<u64> = Item::new("paid_count");
+pub const STOPPED_COUNT: Item<u64> = Item::new("stopped_count");
 
-// multiple-item map
-pub const PAYMENTS: Map<U64Key, PaymentState> = Map::new("payments");
+// Id of the last payment paid by a Pay that hit its limit, the next Pay resumes
+// after it. Zero once a sweep gets through every payment
+pub const LAST_SWEPT_ID: Item<u64> = Item::new("last_swept_id");
 
-pub fn next_id(store: &mut dyn Storage) -> StdResult<u64> {
-    let id: u64 = PAYMENT_COUNT.may_load(store)?.unwrap_or_default() + 1;
+// batch_ids of AddPayments calls already processed
+pub const PROCESSED_BATCHES: Map<&str, ()> = Map::new("processed_batches");
+
+// Payment state from before an in flight payout, restored if the payout fails
+pub const PAYOUT_ROLLBACK: Map<U64Key, PaymentState> = Map::new("payout_rollback");
+
+// Balance an in flight cw20 transfer must leave its recipient with, or the
+// payout is rolled back as if it failed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Cw20Delivery {
+    pub token: Addr,
+    pub recipient: Addr,
+    pub min_balance: Uint128,
+}
+
+pub const PAYOUT_DELIVERY: Map<U64Key, Cw20Delivery> = Map::new("payout_delivery");
+
+// Fee on an in flight payout, sent to the collector once the payout succeeds
+pub const PAYOUT_FEE: Map<U64Key, Payment> = Map::new("payout_fee");
+
+// Running totals behind the Stats query, updated as payments are added, paid
+// and stopped so it doesn't scan every payment
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Stats {
+    pub scheduled: Totals,
+    pub paid: Totals,
+    // Remainders of stopped payments sent back to the owner
+    pub refunded: Totals,
+    // Still owed by payments that are neither paid nor stopped
+    pub active: Totals,
+    pub distinct_recipients: u64,
+}
+
+pub const STATS: Item<Stats> = Item::new("stats");
+
+// Every recipient ever scheduled, so each is only counted once
+pub const SEEN_RECIPIENTS: Map<&Addr, ()> = Map::new("seen_recipients");
+
+// Owner and recipient approvals of payments with require_approval
+pub const APPROVALS: Map<(U64Key, &Addr), ()> = Map::new("approvals");
+
+// Address a beneficiary's payouts go to instead of payment.recipient
+pub const PAYOUT_ADDRESSES: Map<&Addr, Addr> = Map::new("payout_addresses");
+
+pub fn next_id(store: &mut dyn Storage) -> Result<u64, ContractError> {
+    let id: u64 = PAYMENT_COUNT
+        .may_load(store)?
+        .unwrap_or_default()
+        .checked_add(1)
+        .ok_or(ContractError::Overflow {})?;
+    // A counter set behind the contract's back must not overwrite a payment
+    if payments().may_load(store, id.into())?.is_some() {
+        return Err(ContractError::IdCollision {});
+    }
     PAYMENT_COUNT.save(store, &id)?;
     Ok(id)
 }
+
+pub fn increment_count(store: &mut dyn Storage, counter: &Item<u64>) -> StdResult<()> {
+    let count = counter.may_load(store)?.unwrap_or_default();
+    counter.save(store, &(count + 1))
+}
+
+pub fn decrement_count(store: &mut dyn Storage, counter: &Item<u64>) -> StdResult<()> {
+    let count = counter.may_load(store)?.unwrap_or_default();
+    counter.save(store, &count.saturating_sub(1))
+}
+
+// Saves every payment again so the indexes cover payments stored before them.
+// Saving a payment replaces its index entries, so this is safe to repeat
+pub fn migrate_payment_indexes(store: &mut dyn Storage) -> StdResult<()> {
+    let stored = payments()
+        .range(store, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, p) in stored {
+        payments().save(store, p.id.into(), &p)?;
+    }
+    Ok(())
+}
+
+// Counts paid and stopped payments if the counters have never been set
+pub fn migrate_status_counts(store: &mut dyn Storage) -> StdResult<()> {
+    if PAID_COUNT.may_load(store)?.is_some() {
+        return Ok(());
+    }
+    let (mut paid, mut stopped) = (0u64, 0u64);
+    for p in payments().range(store, None, None, Order::Ascending) {
+        let (_, p) = p?;
+        paid += p.paid as u64;
+        stopped += p.stopped as u64;
+    }
+    PAID_COUNT.save(store, &paid)?;
+    STOPPED_COUNT.save(store, &stopped)
+}
+
+// Moves the payment counter from the legacy key if it is still set
+pub fn migrate_payment_count(store: &mut dyn Storage) -> StdResult<()> {
+    if let Some(count) = LEGACY_PAYMENT_COUNT.may_load(store)? {
+        PAYMENT_COUNT.save(store, &count)?;
+        LEGACY_PAYMENT_COUNT.remove(store);
+    }
+    Ok(())
+}